@@ -0,0 +1,131 @@
+//! arrows are limited. each shot takes some out of the quiver, and arrows that came to a stop, in
+//! the terrain or on the ground, can be walked over to put them back
+
+use bevy::prelude::*;
+use bevy_ggrs::{Rollback, RollbackOrdered};
+
+use crate::{
+    collision::{hitbox_intersects, Hitbox},
+    component::{Bullet, Health, Lifetime, Player, Position, Velocity},
+    fixed::{Fixed, FixedVec2},
+    weapon::Weapon,
+    GameFrameCount,
+};
+
+/// arrows a player starts each round with, and can carry at most
+pub const QUIVER_SIZE: u8 = 12;
+/// frames between two arrows turning up in everyone's quiver, so nobody runs dry for good
+const RESUPPLY_INTERVAL: u64 = 300;
+/// how close to a stuck arrow a player has to get to pick it up
+const PICKUP_REACH: Fixed = Fixed::from_int(6);
+/// frames an arrow that fell short, or bounced about until it stopped, lies around for
+pub const DROPPED_LINGER: usize = 600;
+
+pub struct AmmoPlugin;
+impl Plugin for AmmoPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Quiver>();
+    }
+}
+
+/// the arrows a player has left
+#[derive(Component, Clone, Copy, Debug, Reflect, Hash)]
+pub struct Quiver {
+    pub arrows: u8,
+}
+
+impl Default for Quiver {
+    fn default() -> Self {
+        Self {
+            arrows: QUIVER_SIZE,
+        }
+    }
+}
+
+impl Quiver {
+    /// enough arrows for a shot of `weapon`, which takes one per projectile
+    pub fn holds(&self, weapon: &Weapon) -> bool {
+        self.arrows >= weapon.projectiles
+    }
+}
+
+/// bring a flying arrow to a stop where it is, leaving it to be picked up for `linger` frames
+pub fn stop_arrow(
+    bullet: &mut Bullet,
+    velocity: &mut Velocity,
+    lifetime: &mut Lifetime,
+    linger: usize,
+) {
+    velocity.0 = FixedVec2::ZERO;
+    lifetime.0 = linger;
+    bullet.stuck = true;
+}
+
+/// players walking over arrows that came to a stop put them back in their quiver
+pub fn pick_up_arrows(
+    mut commands: Commands,
+    order: Res<RollbackOrdered>,
+    q_arrow: Query<(Entity, &Rollback, &Bullet, &Position), Without<Player>>,
+    mut q_player: Query<(&Player, &Hitbox, &Position, &Health, &mut Quiver), Without<Bullet>>,
+) {
+    // whoever gets an arrow both are standing on mustn't depend on query order
+    let mut arrows: Vec<_> = q_arrow
+        .iter()
+        .filter(|(_, _, bullet, _)| bullet.stuck)
+        .collect();
+    arrows.sort_by_key(|(_, rollback, ..)| order.order(**rollback));
+    let mut players: Vec<_> = q_player.iter_mut().collect();
+    players.sort_by_key(|(player, ..)| player.id);
+    let reach = Hitbox::Circle {
+        offset: FixedVec2::ZERO,
+        radius: PICKUP_REACH,
+    };
+    let mut taken = vec![];
+    for (_, p_hitbox, p_pos, health, mut quiver) in players {
+        if health.0 <= 0 {
+            continue;
+        }
+        for &(entity, _, _, a_pos) in &arrows {
+            if quiver.arrows >= QUIVER_SIZE {
+                break;
+            }
+            if taken.contains(&entity) {
+                continue;
+            }
+            if hitbox_intersects((p_hitbox, p_pos.0), (&reach, a_pos.0)) {
+                quiver.arrows += 1;
+                commands.entity(entity).despawn();
+                taken.push(entity);
+            }
+        }
+    }
+}
+
+/// every so often, everyone short of a full quiver gets an arrow back
+pub fn resupply(frame: Res<GameFrameCount>, mut q_quiver: Query<&mut Quiver>) {
+    if frame.0 == 0 || !frame.0.is_multiple_of(RESUPPLY_INTERVAL) {
+        return;
+    }
+    for mut quiver in &mut q_quiver {
+        if quiver.arrows < QUIVER_SIZE {
+            quiver.arrows += 1;
+        }
+    }
+}
+
+#[test]
+fn quivers_refill_slowly_up_to_full() {
+    let mut world = World::new();
+    world.insert_resource(GameFrameCount(0));
+    let player = world.spawn(Quiver { arrows: 0 }).id();
+    let mut schedule = Schedule::default();
+    schedule.add_systems(resupply);
+    for frame in 0..RESUPPLY_INTERVAL * 20 {
+        world.resource_mut::<GameFrameCount>().0 = frame;
+        schedule.run(&mut world);
+        if frame == RESUPPLY_INTERVAL * 2 {
+            assert_eq!(world.get::<Quiver>(player).unwrap().arrows, 2);
+        }
+    }
+    assert_eq!(world.get::<Quiver>(player).unwrap().arrows, QUIVER_SIZE);
+}
@@ -23,7 +23,7 @@ impl AnimationIndices {
         }
     }
 
-    pub fn from_frames(frames: &Vec<Frame>) -> Self {
+    pub fn from_frames(frames: &[Frame]) -> Self {
         Self {
             frames: frames.iter().map(|f| f.tile_id as usize).collect(),
             flip_x: false,
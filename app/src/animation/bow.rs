@@ -4,6 +4,8 @@ use super::AnimationIndices;
 pub enum BowAnimation {
     Empty,
     Draw,
+    /// drawn this far, from 1 (nocked) to 3 (fully drawn), while a shot charges
+    Charge(usize),
 }
 
 impl From<BowAnimation> for AnimationIndices {
@@ -11,6 +13,7 @@ impl From<BowAnimation> for AnimationIndices {
         match anim {
             BowAnimation::Empty => AnimationIndices::from_range(0, 0),
             BowAnimation::Draw => AnimationIndices::from_range(1, 3),
+            BowAnimation::Charge(frame) => AnimationIndices::from_range(frame, frame),
         }
     }
 }
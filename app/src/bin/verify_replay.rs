@@ -0,0 +1,143 @@
+//! replays a match headlessly, printing a checksum of the rollback state for every frame and the
+//! final scores. two runs of the same replay must print the same thing, on any machine.
+//!
+//! usage: `verify_replay <file.replay>`
+
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+use app::{
+    checksum::FrameChecksums,
+    component::{Player, Points},
+    config::LaunchOptions,
+    replay::{Replay, ReplayPlayback, WatchReplay},
+    GamePlugin, GameState, InspectFrame,
+};
+use bevy::{
+    audio::{AudioLoader, AudioPlugin},
+    log::{Level, LogPlugin},
+    prelude::*,
+    render::{settings::WgpuSettings, RenderPlugin},
+    time::TimeUpdateStrategy,
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+use bevy_ggrs::{GgrsSchedule, RollbackFrameCount};
+
+/// the clock never runs faster than this per update, see `Time::<Virtual>::max_delta`
+const STEP: Duration = Duration::from_millis(250);
+
+/// wall clock time allowed for loading the map and sprites
+const LOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `(frame, checksum)` for every simulated frame, in order
+#[derive(Resource, Default)]
+struct Checksums(Vec<(i32, u64)>);
+
+fn main() -> ExitCode {
+    let Some(path) = std::env::args().nth(1).map(PathBuf::from) else {
+        eprintln!("usage: verify_replay <file.replay>");
+        return ExitCode::FAILURE;
+    };
+    // fail early and readably rather than from inside the app
+    let replay = match Replay::load(&path) {
+        Ok(replay) => replay,
+        Err(e) => {
+            eprintln!("{}: {e}", path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut app = App::new();
+    app.insert_resource(LaunchOptions::default())
+        .insert_resource(WatchReplay(path))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(STEP))
+        .init_resource::<Checksums>()
+        .add_plugins(
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    close_when_requested: false,
+                })
+                .set(RenderPlugin {
+                    render_creation: WgpuSettings {
+                        backends: None,
+                        ..default()
+                    }
+                    .into(),
+                })
+                .set(AssetPlugin {
+                    mode: AssetMode::Unprocessed,
+                    ..default()
+                })
+                .set(LogPlugin {
+                    level: Level::WARN,
+                    ..default()
+                })
+                .disable::<WinitPlugin>()
+                .disable::<AudioPlugin>(),
+        )
+        // the game still loads its sounds, it just never plays them
+        .init_asset::<AudioSource>()
+        .init_asset_loader::<AudioLoader>()
+        .add_plugins(GamePlugin)
+        .add_systems(GgrsSchedule, record_checksum.in_set(InspectFrame));
+    app.finish();
+    app.cleanup();
+
+    let started = std::time::Instant::now();
+    while !app
+        .world
+        .get_resource::<ReplayPlayback>()
+        .is_some_and(|playback| playback.finished)
+    {
+        app.update();
+        let state = app.world.resource::<State<GameState>>();
+        match state.get() {
+            GameState::Loading if started.elapsed() > LOAD_TIMEOUT => {
+                eprintln!("assets did not load, is BEVY_ASSET_ROOT set to the repository root?");
+                return ExitCode::FAILURE;
+            }
+            // assets load in the background
+            GameState::Loading => std::thread::sleep(Duration::from_millis(1)),
+            // start_playback gave up on the replay
+            GameState::Lobby
+                if !app.world.contains_resource::<WatchReplay>()
+                    && !app.world.contains_resource::<ReplayPlayback>() =>
+            {
+                eprintln!("replay could not be played");
+                return ExitCode::FAILURE;
+            }
+            _ => {}
+        }
+    }
+
+    for (frame, checksum) in &app.world.resource::<Checksums>().0 {
+        println!("frame {frame} {checksum:016x}");
+    }
+    let mut players: Vec<(usize, u32)> = app
+        .world
+        .query::<(&Player, &Points)>()
+        .iter(&app.world)
+        .map(|(player, points)| (player.id, points.0))
+        .collect();
+    players.sort_unstable();
+    for (id, points) in players {
+        println!("player {} {points}", id + 1);
+    }
+    if app.world.resource::<Checksums>().0.len() != replay.frames.len() {
+        eprintln!(
+            "simulated {} of {} frames",
+            app.world.resource::<Checksums>().0.len(),
+            replay.frames.len()
+        );
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn record_checksum(world: &mut World) {
+    let frame = i32::from(*world.resource::<RollbackFrameCount>());
+    let checksum = FrameChecksums::of(world).combined();
+    world.resource_mut::<Checksums>().0.push((frame, checksum));
+}
@@ -0,0 +1,349 @@
+use bevy::prelude::*;
+use bevy_ggrs::PlayerInputs;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ammo::Quiver,
+    component::{Bullet, CanShoot, Health, Player, Position, Velocity},
+    config::LaunchOptions,
+    fixed::{Fixed, FixedVec2},
+    input::{PlayerInput, FIRE, MOVE, REMATCH},
+    p2p::GgrsConfig,
+    rounds::{MatchState, Phase},
+    weapon::{Arsenal, Equipped},
+    GameFrameCount,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default, Reflect)]
+pub enum BotDifficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl BotDifficulty {
+    pub const ALL: [Self; 3] = [Self::Easy, Self::Normal, Self::Hard];
+
+    /// frames between looking at the world and acting on it
+    fn reaction_frames(self) -> u8 {
+        match self {
+            Self::Easy => 24,
+            Self::Normal => 12,
+            Self::Hard => 4,
+        }
+    }
+
+    /// largest aim mistake, in steps of the 0 - 255 input angle
+    fn aim_error(self) -> Fixed {
+        match self {
+            Self::Easy => Fixed::from_int(14),
+            Self::Normal => Fixed::from_int(6),
+            Self::Hard => Fixed::from_ratio(8, 5),
+        }
+    }
+
+    /// how much of a moving target's path is accounted for
+    fn lead(self) -> Fixed {
+        match self {
+            Self::Easy => Fixed::ZERO,
+            Self::Normal => Fixed::from_ratio(3, 5),
+            Self::Hard => Fixed::ONE,
+        }
+    }
+}
+
+impl std::str::FromStr for BotDifficulty {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "easy" => Ok(Self::Easy),
+            "normal" => Ok(Self::Normal),
+            "hard" => Ok(Self::Hard),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for BotDifficulty {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// bots taking up the last player slots of a match. every peer has to agree on these
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct BotSettings {
+    pub count: usize,
+    pub difficulty: BotDifficulty,
+}
+
+impl BotSettings {
+    pub fn from_options(options: &LaunchOptions) -> Self {
+        Self {
+            count: options.parse("bots").unwrap_or(0),
+            difficulty: options.parse("bot-difficulty").unwrap_or_default(),
+        }
+    }
+}
+
+/// a player driven by `bot_input` instead of a device
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Bot {
+    pub difficulty: BotDifficulty,
+}
+
+/// what a bot decided the last time it looked, acted on until it looks again
+#[derive(Component, Clone, Copy, Default, Debug, Reflect, Hash)]
+pub struct BotBrain {
+    pub dir: u8,
+    pub moving: bool,
+    pub angle: u8,
+    pub fire: bool,
+    /// frames until the bot looks again
+    pub think_in: u8,
+}
+
+/// what a bot knows of another player
+#[derive(Clone, Copy, Debug)]
+struct Target {
+    id: usize,
+    pos: FixedVec2,
+    vel: FixedVec2,
+}
+
+/// stay within this band of distances to the target
+const TOO_CLOSE: Fixed = Fixed::from_int(80);
+const TOO_FAR: Fixed = Fixed::from_int(160);
+/// arrows passing closer than this are dodged
+const DODGE_RADIUS: Fixed = Fixed::from_int(14);
+/// frames ahead that arrows are worth worrying about
+const DODGE_HORIZON: Fixed = Fixed::from_int(45);
+
+/// replace the inputs of bot players with their own decisions.
+/// this only reads rollback state, so every peer comes to the same conclusions
+pub fn bot_input(
+    mut inputs: ResMut<PlayerInputs<GgrsConfig>>,
+    frame: Res<GameFrameCount>,
+    arsenal: Res<Arsenal>,
+    state: Res<MatchState>,
+    mut q_bots: Query<(
+        &Player,
+        &Position,
+        &CanShoot,
+        &Quiver,
+        &Equipped,
+        &Bot,
+        &mut BotBrain,
+    )>,
+    q_players: Query<(&Player, &Position, &Velocity, &Health)>,
+    q_arrows: Query<(&Bullet, &Position, &Velocity)>,
+) {
+    let mut targets: Vec<Target> = q_players
+        .iter()
+        .filter(|(.., health)| health.0 > 0)
+        .map(|(player, pos, vel, _)| Target {
+            id: player.id,
+            pos: pos.0,
+            vel: vel.0,
+        })
+        .collect();
+    // query order isn't necessarily the same on every peer
+    targets.sort_by_key(|target| target.id);
+
+    for (player, pos, can_shoot, quiver, equipped, bot, mut brain) in &mut q_bots {
+        let weapon = arsenal.get(equipped);
+        if brain.think_in == 0 {
+            let others: Vec<Target> = targets
+                .iter()
+                .copied()
+                .filter(|target| target.id != player.id)
+                .collect();
+            let mut arrows: Vec<(FixedVec2, FixedVec2)> = q_arrows
+                .iter()
+                .filter(|(bullet, ..)| bullet.shot_by != player.id && !bullet.stuck)
+                .map(|(_, pos, vel)| (pos.0, vel.0))
+                .collect();
+            // which of two equally pressing arrows gets dodged can't depend on query order
+            arrows.sort_unstable_by_key(|(pos, vel)| (pos.x, pos.y, vel.x, vel.y));
+            *brain = decide(
+                player.id,
+                pos.0,
+                brain.angle,
+                bot.difficulty,
+                weapon.speed,
+                weapon.range(),
+                &others,
+                &arrows,
+                frame.0,
+            );
+        } else {
+            brain.think_in -= 1;
+        }
+
+        let mut btn = 0;
+        if brain.moving {
+            btn |= MOVE;
+        }
+        // let go of the trigger in between shots, or the bow never reloads. charged shots are
+        // held until they're at full strength, and go off on letting go
+        let ready =
+            can_shoot.value && can_shoot.since_last >= weapon.cooldown && quiver.holds(weapon);
+        let charged = weapon.charge_frames > 0 && can_shoot.charge >= weapon.charge_frames;
+        if brain.fire && ready && !charged {
+            btn |= FIRE;
+        }
+        // bots are up for anything, and vote like everyone else so replays see it
+        if state.phase == Phase::MatchEnd {
+            btn |= REMATCH;
+        }
+        inputs[player.id].0 = PlayerInput {
+            dir: brain.dir,
+            btn,
+            angle: brain.angle,
+        };
+    }
+}
+
+/// a bot's next move, from where everyone is
+fn decide(
+    id: usize,
+    pos: FixedVec2,
+    angle: u8,
+    difficulty: BotDifficulty,
+    speed: Fixed,
+    range: Fixed,
+    targets: &[Target],
+    arrows: &[(FixedVec2, FixedVec2)],
+    frame: u64,
+) -> BotBrain {
+    let mut brain = BotBrain {
+        // keep looking the same way when there's no one left to aim at
+        angle,
+        think_in: difficulty.reaction_frames(),
+        ..default()
+    };
+
+    let target = targets
+        .iter()
+        .min_by_key(|target| target.pos.distance_squared(pos));
+
+    let movement = if let Some(dodge) = dodge(pos, arrows) {
+        Some(dodge)
+    } else if let Some(target) = target {
+        let to_target = target.pos - pos;
+        let dist = to_target.length();
+        if dist > TOO_FAR {
+            Some(to_target)
+        } else if dist < TOO_CLOSE {
+            Some(-to_target)
+        } else {
+            // circle the target, changing direction every now and then
+            let side = if (frame / 90 + id as u64).is_multiple_of(2) {
+                to_target
+            } else {
+                -to_target
+            };
+            Some(side.perp())
+        }
+    } else {
+        None
+    };
+    if let Some(movement) = movement.filter(|m| *m != FixedVec2::ZERO) {
+        brain.moving = true;
+        brain.dir = movement.to_angle();
+    }
+
+    if let Some(target) = target {
+        let dist = target.pos.distance(pos);
+        let flight = dist / speed;
+        let aim_at = target.pos + target.vel * (flight * difficulty.lead());
+        let error = noise(frame / difficulty.reaction_frames() as u64, id) * difficulty.aim_error();
+        // 255 is a full turn
+        brain.angle = ((aim_at - pos).to_angle() as i64 + error.round()).rem_euclid(255) as u8;
+        // don't waste arrows that won't make it
+        brain.fire = dist < range * Fixed::from_ratio(3, 5);
+    }
+    brain
+}
+
+/// which way to step out of the way of the most pressing arrow, if any is on course
+fn dodge(pos: FixedVec2, arrows: &[(FixedVec2, FixedVec2)]) -> Option<FixedVec2> {
+    arrows
+        .iter()
+        .filter_map(|&(arrow, vel)| {
+            let speed_sq = vel.length_squared();
+            if speed_sq == Fixed::ZERO {
+                return None;
+            }
+            // frames until the arrow is closest to us, and how close it gets
+            let t = (pos - arrow).dot(vel) / speed_sq;
+            if !(Fixed::ZERO..DODGE_HORIZON).contains(&t) {
+                return None;
+            }
+            let miss = pos - (arrow + vel * t);
+            if miss.length() > DODGE_RADIUS {
+                return None;
+            }
+            // step to whichever side of its path we're already on
+            let side = if vel.perp_dot(miss) < Fixed::ZERO {
+                -vel
+            } else {
+                vel
+            };
+            Some((t, side.perp()))
+        })
+        .min_by_key(|(t, _)| *t)
+        .map(|(_, dir)| dir)
+}
+
+/// a deterministic pseudo-random number in [-1, 1)
+fn noise(step: u64, id: usize) -> Fixed {
+    // splitmix64
+    let mut z = step
+        .wrapping_add((id as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+        .wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    // 17 random bits, twice as many as there are fractional ones
+    Fixed((z >> 47) as i64) - Fixed::ONE
+}
+
+#[test]
+fn bots_sidestep_incoming_arrows() {
+    // an arrow flying straight down at us
+    let from = FixedVec2::new(Fixed::ZERO, Fixed::from_int(50));
+    let speed = Fixed::from_ratio(5, 2);
+    let arrows = [(from, FixedVec2::new(Fixed::ZERO, -speed))];
+    let dir = dodge(FixedVec2::ZERO, &arrows).unwrap();
+    assert_eq!(dir.y, Fixed::ZERO);
+    // one flying away is no threat
+    let away = [(from, FixedVec2::new(Fixed::ZERO, speed))];
+    assert!(dodge(FixedVec2::ZERO, &away).is_none());
+}
+
+#[test]
+fn bots_close_in_on_distant_targets() {
+    let target = Target {
+        id: 1,
+        pos: FixedVec2::new(Fixed::from_int(200), Fixed::ZERO),
+        vel: FixedVec2::ZERO,
+    };
+    let brain = decide(
+        0,
+        FixedVec2::ZERO,
+        0,
+        BotDifficulty::Hard,
+        Fixed::from_ratio(5, 2),
+        Fixed::from_int(375),
+        &[target],
+        &[],
+        0,
+    );
+    assert!(brain.moving);
+    // a quarter turn clockwise from up
+    assert_eq!(brain.dir, 64);
+    assert!(brain.fire);
+}
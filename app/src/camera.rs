@@ -45,9 +45,23 @@ pub fn spawn_primary(mut commands: Commands) {
         .insert(FollowPlayer);
 }
 
-/// sets the camera to follow the local player, stopping at the tilemap boundaries
+/// what the camera looks at when there is no local player, i.e. while spectating
+#[derive(Resource, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum SpectatorCamera {
+    #[default]
+    Free,
+    Following(usize),
+}
+
+/// stop following whoever was spectated last match
+pub fn reset_spectator_camera(mut spectator_camera: ResMut<SpectatorCamera>) {
+    *spectator_camera = SpectatorCamera::default();
+}
+
+/// sets the camera to follow the local player (or the spectated one), stopping at the tilemap boundaries
 pub fn follow_player(
     local_player_id: Option<Res<LocalPlayer>>,
+    spectator_camera: Res<SpectatorCamera>,
     q_player: Query<(&Player, &Transform)>,
     mut q_camera: Query<
         (&mut Transform, &OrthographicProjection),
@@ -55,38 +69,162 @@ pub fn follow_player(
     >,
     q_map: Query<(&Aabb, &Transform), (With<Tilemap>, Without<Camera>, Without<Player>)>,
 ) {
-    let Some(id) = local_player_id else {
-        return;
+    let id = match (local_player_id, *spectator_camera) {
+        (Some(local_player), _) => local_player.id,
+        (None, SpectatorCamera::Following(id)) => id,
+        (None, SpectatorCamera::Free) => return,
     };
     // tilemap aabb relative to itself
     let Ok((map_aabb, map_transform)) = q_map.get_single() else {
         return;
     };
     for (player, player_transform) in &q_player {
-        if player.id != id.id {
+        if player.id != id {
             continue;
         }
 
         for (mut transform, proj) in &mut q_camera {
             let player_pos = player_transform.translation.truncate();
-            let viewport_area = proj.area;
+            let clamped = clamp_to_map(player_pos, proj, map_aabb, map_transform);
+            transform.translation.x = clamped.x;
+            transform.translation.y = clamped.y;
+        }
+    }
+}
 
-            let map_center = map_transform
-                .transform_point(map_aabb.center.into())
-                .truncate();
-            let map_halfsize = (map_transform.scale * Vec3::from(map_aabb.half_extents)).truncate();
+/// with everyone on one screen, keep all players in view, zooming out as they spread apart
+pub fn frame_local_players(
+    time: Res<Time>,
+    q_player: Query<&Transform, With<Player>>,
+    mut q_camera: Query<
+        (&mut Transform, &mut OrthographicProjection, Has<MainCamera>),
+        (With<FollowPlayer>, With<Camera>, Without<Player>),
+    >,
+    q_map: Query<(&Aabb, &Transform), (With<Tilemap>, Without<Camera>, Without<Player>)>,
+) {
+    // room around the outermost players, so they can see what's coming
+    const MARGIN: f32 = 64.;
+    const ZOOM_SPEED: f32 = 4.;
 
-            // map boundary in world coordinates
-            let map_min = map_center - map_halfsize;
-            let map_max = map_center + map_halfsize;
+    let Ok((map_aabb, map_transform)) = q_map.get_single() else {
+        return;
+    };
+    let mut positions = q_player.iter().map(|t| t.translation.truncate());
+    let Some(first) = positions.next() else {
+        return;
+    };
+    let (min, max) = positions.fold((first, first), |(min, max), pos| {
+        (min.min(pos), max.max(pos))
+    });
+    let map_size = (map_transform.scale * Vec3::from(map_aabb.half_extents)).truncate() * 2.;
+
+    for (mut transform, mut proj, is_main) in &mut q_camera {
+        if is_main {
+            // the view size at a scale of 1
+            let base = proj.area.size() / proj.scale;
+            let wanted = (max - min + Vec2::splat(MARGIN * 2.)) / base;
+            // never show past the map edges
+            let zoom = wanted
+                .max_element()
+                .min((map_size / base).min_element())
+                .max(1.);
+            let t = (ZOOM_SPEED * time.delta_seconds()).min(1.);
+            proj.scale += (zoom - proj.scale) * t;
+        }
+        let clamped = clamp_to_map((min + max) / 2., &proj, map_aabb, map_transform);
+        transform.translation.x = clamped.x;
+        transform.translation.y = clamped.y;
+    }
+}
+
+/// back to the regular view after a local match
+pub fn reset_zoom(mut q_camera: Query<&mut OrthographicProjection, With<MainCamera>>) {
+    for mut proj in &mut q_camera {
+        proj.scale = 1.;
+    }
+}
 
-            let camera_min = map_min + viewport_area.size() / 2.;
-            let camera_max = map_max - viewport_area.size() / 2.;
+/// spectator controls: tab cycles through players, space detaches into a free camera moved with wasd / arrows
+pub fn spectator_camera_controls(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mut spectator_camera: ResMut<SpectatorCamera>,
+    q_player: Query<&Player>,
+    mut q_camera: Query<
+        (&mut Transform, &OrthographicProjection),
+        (With<FollowPlayer>, With<Camera>, Without<Player>),
+    >,
+    q_map: Query<(&Aabb, &Transform), (With<Tilemap>, Without<Camera>, Without<Player>)>,
+) {
+    const PAN_SPEED: f32 = 200.;
 
-            transform.translation.x = player_pos.x.clamp(camera_min.x, camera_max.x);
-            transform.translation.y = player_pos.y.clamp(camera_min.y, camera_max.y);
+    if keys.just_pressed(KeyCode::Tab) {
+        let mut ids: Vec<usize> = q_player.iter().map(|p| p.id).collect();
+        ids.sort();
+        let next = match *spectator_camera {
+            SpectatorCamera::Following(id) => ids.iter().find(|other| **other > id),
+            SpectatorCamera::Free => None,
+        }
+        .or(ids.first());
+        if let Some(id) = next {
+            *spectator_camera = SpectatorCamera::Following(*id);
         }
     }
+    if keys.just_pressed(KeyCode::Space) {
+        *spectator_camera = SpectatorCamera::Free;
+    }
+    if *spectator_camera != SpectatorCamera::Free {
+        return;
+    }
+
+    let mut dir = Vec2::ZERO;
+    if keys.any_pressed([KeyCode::A, KeyCode::Left]) {
+        dir.x -= 1.;
+    }
+    if keys.any_pressed([KeyCode::D, KeyCode::Right]) {
+        dir.x += 1.;
+    }
+    if keys.any_pressed([KeyCode::W, KeyCode::Up]) {
+        dir.y += 1.;
+    }
+    if keys.any_pressed([KeyCode::S, KeyCode::Down]) {
+        dir.y -= 1.;
+    }
+    let Ok((map_aabb, map_transform)) = q_map.get_single() else {
+        return;
+    };
+    let delta = dir.normalize_or_zero() * PAN_SPEED * time.delta_seconds();
+    for (mut transform, proj) in &mut q_camera {
+        let pos = transform.translation.truncate() + delta;
+        let clamped = clamp_to_map(pos, proj, map_aabb, map_transform);
+        transform.translation.x = clamped.x;
+        transform.translation.y = clamped.y;
+    }
+}
+
+/// clamp a camera position so that its view stays inside the tilemap
+fn clamp_to_map(
+    pos: Vec2,
+    proj: &OrthographicProjection,
+    map_aabb: &Aabb,
+    map_transform: &Transform,
+) -> Vec2 {
+    let viewport_area = proj.area;
+
+    let map_center = map_transform
+        .transform_point(map_aabb.center.into())
+        .truncate();
+    let map_halfsize = (map_transform.scale * Vec3::from(map_aabb.half_extents)).truncate();
+
+    // map boundary in world coordinates
+    let map_min = map_center - map_halfsize;
+    let map_max = map_center + map_halfsize;
+
+    let camera_min = map_min + viewport_area.size() / 2.;
+    // a view wider than the map (i.e. zoomed all the way out) sticks to its bottom left
+    let camera_max = (map_max - viewport_area.size() / 2.).max(camera_min);
+
+    pos.clamp(camera_min, camera_max)
 }
 
 /// spawns a tiny minimap in the top-left corner
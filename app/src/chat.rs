@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, utils::HashSet};
+use bevy_ggrs::Session;
+use bevy_matchbox::prelude::PeerId;
+
+use crate::{
+    p2p::{player_handles, GameSocket, GgrsConfig, PeerRoles, Role, CHAT_CHANNEL},
+    GameState,
+};
+
+/// longest message that is sent or accepted, in characters
+pub const MAX_MESSAGE_LEN: usize = 200;
+/// lines kept in the chat log
+const HISTORY_LEN: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct ChatLine {
+    /// `None` for our own messages
+    pub from: Option<PeerId>,
+    pub name: String,
+    pub text: String,
+}
+
+/// chat of the current room, kept after a match so it can still be read in the lobby
+#[derive(Resource, Default, Debug)]
+pub struct ChatLog {
+    pub lines: VecDeque<ChatLine>,
+    pub muted: HashSet<PeerId>,
+}
+
+impl ChatLog {
+    fn push(&mut self, line: ChatLine) {
+        self.lines.push_back(line);
+        while self.lines.len() > HISTORY_LEN {
+            self.lines.pop_front();
+        }
+    }
+}
+
+pub struct ChatPlugin;
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChatLog>()
+            // a new room, a new conversation
+            .add_systems(OnEnter(GameState::Connecting), |mut commands: Commands| {
+                commands.insert_resource(ChatLog::default())
+            })
+            .add_systems(Update, receive_chat.run_if(resource_exists::<GameSocket>()));
+    }
+}
+
+/// how a peer shows up in chat: their player number once in game, their role before that
+pub fn peer_name(
+    peer: PeerId,
+    session: Option<&Session<GgrsConfig>>,
+    roles: Option<&PeerRoles>,
+) -> String {
+    if let Some(handle) = session.and_then(|s| player_handles(s, peer).first().copied()) {
+        return format!("Player {}", handle + 1);
+    }
+    // uuids are long, the start of one is plenty to tell peers apart
+    let short: String = peer.0.to_string().chars().take(4).collect();
+    match roles.and_then(|roles| roles.0.get(&peer)) {
+        Some(Role::Spectator) => format!("Spectator {short}"),
+        _ => format!("Peer {short}"),
+    }
+}
+
+fn receive_chat(
+    mut socket: ResMut<GameSocket>,
+    mut log: ResMut<ChatLog>,
+    session: Option<Res<Session<GgrsConfig>>>,
+    roles: Option<Res<PeerRoles>>,
+) {
+    let Ok(channel) = socket.get_channel(CHAT_CHANNEL) else {
+        return;
+    };
+    for (peer, packet) in channel.receive() {
+        if log.muted.contains(&peer) {
+            continue;
+        }
+        let Ok(text) = std::str::from_utf8(&packet) else {
+            warn!("dropping malformed chat message from {peer}");
+            continue;
+        };
+        let text: String = text.chars().take(MAX_MESSAGE_LEN).collect();
+        let name = peer_name(peer, session.as_deref(), roles.as_deref());
+        log.push(ChatLine {
+            from: Some(peer),
+            name,
+            text,
+        });
+    }
+}
+
+/// send a message to every connected peer, muted or not
+pub fn send_chat(socket: &mut GameSocket, log: &mut ChatLog, text: &str) {
+    let text: String = text.trim().chars().take(MAX_MESSAGE_LEN).collect();
+    if text.is_empty() {
+        return;
+    }
+    let peers: Vec<PeerId> = socket.connected_peers().collect();
+    let channel = socket.channel(CHAT_CHANNEL);
+    for peer in peers {
+        channel.send(text.as_bytes().into(), peer);
+    }
+    log.push(ChatLine {
+        from: None,
+        name: "You".to_owned(),
+        text,
+    });
+}
@@ -0,0 +1,178 @@
+use std::hash::{Hash, Hasher};
+
+use bevy::prelude::*;
+use bevy_ggrs::{ChecksumFlag, ChecksumPart, Rollback, RollbackOrdered, SaveWorld, SaveWorldSet};
+
+use crate::{
+    ammo::Quiver, bot::BotBrain, component::*, health::SinceHit, rand::Rng, rounds::MatchState,
+    weapon::Equipped, GameFrameCount,
+};
+
+/// fnv-1a, which unlike the std hasher gives the same result on every platform and build
+pub struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    // usize is 32 bits on wasm, hash it as a u64 so native and web peers agree
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+/// a hash of every rollback entity's `T`, in an order all peers agree on
+fn component_checksum<T: Component>(world: &mut World, hash: fn(&T, &mut StableHasher)) -> u64 {
+    world.resource_scope(|world, order: Mut<RollbackOrdered>| {
+        let mut entries: Vec<(usize, u64)> = world
+            .query::<(&Rollback, &T)>()
+            .iter(world)
+            .map(|(rollback, component)| {
+                let mut state = StableHasher::default();
+                hash(component, &mut state);
+                (order.order(*rollback), state.finish())
+            })
+            .collect();
+        entries.sort_unstable();
+        let mut state = StableHasher::default();
+        for (order, hash) in entries {
+            state.write_usize(order);
+            state.write_u64(hash);
+        }
+        state.finish()
+    })
+}
+
+fn resource_checksum<T: Resource + Hash>(world: &World) -> u64 {
+    let mut state = StableHasher::default();
+    if let Some(resource) = world.get_resource::<T>() {
+        resource.hash(&mut state);
+    }
+    state.finish()
+}
+
+/// per-component hashes of the rollback state at the end of a frame
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct FrameChecksums(pub Vec<(&'static str, u64)>);
+
+impl FrameChecksums {
+    pub fn of(world: &mut World) -> Self {
+        Self(vec![
+            (
+                "Position",
+                component_checksum::<Position>(world, Hash::hash),
+            ),
+            (
+                "Velocity",
+                component_checksum::<Velocity>(world, Hash::hash),
+            ),
+            ("Health", component_checksum::<Health>(world, Hash::hash)),
+            (
+                "SinceHit",
+                component_checksum::<SinceHit>(world, Hash::hash),
+            ),
+            ("Points", component_checksum::<Points>(world, Hash::hash)),
+            (
+                "RoundWins",
+                component_checksum::<RoundWins>(world, Hash::hash),
+            ),
+            (
+                "CanShoot",
+                component_checksum::<CanShoot>(world, Hash::hash),
+            ),
+            (
+                "Lifetime",
+                component_checksum::<Lifetime>(world, Hash::hash),
+            ),
+            (
+                "InputAngle",
+                component_checksum::<InputAngle>(world, Hash::hash),
+            ),
+            (
+                "WallContactState",
+                component_checksum::<WallContactState>(world, Hash::hash),
+            ),
+            (
+                "LastDamagedBy",
+                component_checksum::<LastDamagedBy>(world, Hash::hash),
+            ),
+            (
+                "BotBrain",
+                component_checksum::<BotBrain>(world, Hash::hash),
+            ),
+            (
+                "Equipped",
+                component_checksum::<Equipped>(world, Hash::hash),
+            ),
+            ("Bullet", component_checksum::<Bullet>(world, Hash::hash)),
+            ("Quiver", component_checksum::<Quiver>(world, Hash::hash)),
+            ("Rng", resource_checksum::<Rng>(world)),
+            ("GameFrameCount", resource_checksum::<GameFrameCount>(world)),
+            ("MatchState", resource_checksum::<MatchState>(world)),
+        ])
+    }
+
+    /// names of the components that hash differently in `other`
+    pub fn mismatches(&self, other: &Self) -> Vec<&'static str> {
+        self.0
+            .iter()
+            .zip(&other.0)
+            .filter(|(a, b)| a != b)
+            .map(|((name, _), _)| *name)
+            .collect()
+    }
+
+    /// everything folded into one hash, for when only whether frames match is interesting
+    pub fn combined(&self) -> u64 {
+        let mut state = StableHasher::default();
+        for (_, hash) in &self.0 {
+            state.write_u64(*hash);
+        }
+        state.finish()
+    }
+}
+
+/// feeds `FrameChecksums` into the checksum ggrs compares between peers to detect desyncs.
+/// `checksum_component_with_hash` would hash with ahash and native `usize`s, which wasm and
+/// native builds don't agree on
+pub struct StateChecksumPlugin;
+impl Plugin for StateChecksumPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, |mut commands: Commands| {
+            commands.spawn((
+                ChecksumPart::default(),
+                ChecksumFlag::<FrameChecksums>::default(),
+            ));
+        })
+        .add_systems(SaveWorld, update_checksum.in_set(SaveWorldSet::Checksum));
+    }
+}
+
+fn update_checksum(world: &mut World) {
+    let checksum = FrameChecksums::of(world).combined();
+    let mut parts = world.query_filtered::<&mut ChecksumPart, With<ChecksumFlag<FrameChecksums>>>();
+    for mut part in parts.iter_mut(world) {
+        part.0 = checksum as u128;
+    }
+}
+
+#[test]
+fn stable_hasher_is_fnv1a() {
+    let mut state = StableHasher::default();
+    state.write(b"a");
+    assert_eq!(state.finish(), 0xaf63_dc4c_8601_ec8c);
+}
@@ -2,53 +2,41 @@ use bevy::{
     audio::{PlaybackMode, Volume, VolumeLevel},
     prelude::*,
 };
-use sepax2d::prelude::*;
+use bevy_ggrs::{Rollback, RollbackOrdered};
 
 use crate::{
-    component::{Bullet, Health, LastDamagedBy, Player, Velocity},
+    ammo,
+    component::{Bullet, Health, LastDamagedBy, Lifetime, Player, Position, Velocity},
+    fixed::{Fixed, FixedVec2},
+    health::{HealthRules, SinceHit},
+    weapon::{Arsenal, Impact},
     DebugState,
 };
 
 #[derive(Clone, Copy, Debug, Component, Reflect)]
 pub enum Hitbox {
-    Rect { offset: Vec2, half_size: Vec2 },
-    Circle { offset: Vec2, radius: f32 },
+    Rect {
+        offset: FixedVec2,
+        half_size: FixedVec2,
+    },
+    Circle {
+        offset: FixedVec2,
+        radius: Fixed,
+    },
 }
 
 impl Hitbox {
-    pub fn with_transform(&self, transform: &Transform) -> Hitbox {
-        match self {
-            Hitbox::Rect { offset, half_size } => {
-                let offset = transform.transform_point(offset.extend(0.)).truncate();
-                let half_size = Vec2 {
-                    x: transform.scale.x * half_size.x,
-                    y: transform.scale.y * half_size.y,
-                };
-                Hitbox::Rect { offset, half_size }
-            }
-            Hitbox::Circle { offset, radius } => {
-                let offset = transform.transform_point(offset.extend(0.)).truncate();
-                let scale = transform.scale.max_element();
-                let radius = radius * scale;
-                Hitbox::Circle { offset, radius }
-            }
-        }
-    }
-
-    pub fn into_sepax(&self) -> Box<dyn Shape> {
-        match self {
-            Hitbox::Rect { offset, half_size } => {
-                let top_left = *offset - *half_size;
-                Box::new(AABB {
-                    position: (top_left.x, top_left.y),
-                    width: half_size.x * 2.,
-                    height: half_size.y * 2.,
-                })
-            }
-            Hitbox::Circle { offset, radius } => Box::new(Circle {
-                position: (offset.x, offset.y),
-                radius: *radius,
-            }),
+    /// the hitbox of something at `position`, its offset then being its center in the world
+    pub fn at(&self, position: FixedVec2) -> Hitbox {
+        match *self {
+            Hitbox::Rect { offset, half_size } => Hitbox::Rect {
+                offset: position + offset,
+                half_size,
+            },
+            Hitbox::Circle { offset, radius } => Hitbox::Circle {
+                offset: position + offset,
+                radius,
+            },
         }
     }
 }
@@ -68,15 +56,15 @@ pub struct RigidBody;
 pub struct RigidBodyBundle {
     marker: RigidBody,
     hitbox: Hitbox,
-    transform: TransformBundle,
+    position: Position,
 }
 
 impl RigidBodyBundle {
-    pub fn new(hitbox: Hitbox) -> Self {
+    pub fn new(hitbox: Hitbox, position: FixedVec2) -> Self {
         Self {
             marker: RigidBody,
             hitbox,
-            transform: TransformBundle::IDENTITY,
+            position: Position(position),
         }
     }
 }
@@ -85,15 +73,41 @@ impl RigidBodyBundle {
 pub fn bullet_player_system(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    q_bullet: Query<(Entity, &Bullet, &Hitbox, &Transform), Without<Player>>,
+    rules: Res<HealthRules>,
+    order: Res<RollbackOrdered>,
+    q_bullet: Query<(Entity, &Rollback, &Bullet, &Hitbox, &Position), Without<Player>>,
     mut q_player: Query<
-        (Entity, &Hitbox, &Transform, &mut Health),
-        (With<Player>, Without<Bullet>),
+        (
+            Entity,
+            &Player,
+            &Hitbox,
+            &Position,
+            &mut Health,
+            &mut SinceHit,
+        ),
+        Without<Bullet>,
     >,
 ) {
-    for (p_entity, p_hitbox, p_transform, mut p_health) in &mut q_player {
-        for (b_entity, bullet, b_hitbox, b_transform) in &q_bullet {
-            if hitbox_intersects((p_hitbox, p_transform), (b_hitbox, b_transform)) {
+    // only the first arrow to land counts, and each only lands once, so which ones those are
+    // mustn't depend on query order
+    let mut bullets: Vec<_> = q_bullet
+        .iter()
+        .filter(|(_, _, bullet, ..)| !bullet.stuck)
+        .collect();
+    bullets.sort_by_key(|(_, rollback, ..)| order.order(**rollback));
+    let mut players: Vec<_> = q_player.iter_mut().collect();
+    players.sort_by_key(|(_, player, ..)| player.id);
+    let mut spent = vec![];
+    for (p_entity, _, p_hitbox, p_pos, mut p_health, mut since_hit) in players {
+        for &(b_entity, _, bullet, b_hitbox, b_pos) in &bullets {
+            // arrows fly right through players who were just hit
+            if since_hit.invulnerable(&rules) {
+                break;
+            }
+            if spent.contains(&b_entity) {
+                continue;
+            }
+            if hitbox_intersects((p_hitbox, p_pos.0), (b_hitbox, b_pos.0)) {
                 commands.spawn(AudioBundle {
                     source: asset_server.load("sfx/Damage_1.wav"),
                     settings: PlaybackSettings {
@@ -103,49 +117,101 @@ pub fn bullet_player_system(
                         ..default()
                     },
                 });
-                p_health.0 -= 1;
+                p_health.0 -= bullet.damage;
+                since_hit.0 = 0;
                 commands
                     .entity(p_entity)
                     .insert(LastDamagedBy { id: bullet.shot_by });
                 commands.entity(b_entity).despawn();
+                spent.push(b_entity);
             }
         }
     }
 }
 
-/// reflect bullets that interact with solid terrain
-pub fn _bullet_terrain_system(
-    mut _commands: Commands,
+/// bullets running into solid terrain bounce off, stick in it or break, as their weapon has it
+pub fn bullet_terrain_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    arsenal: Res<Arsenal>,
     mut q_bullet: Query<
-        (Entity, &mut Velocity, &Hitbox, &Transform),
-        (With<Bullet>, Without<RigidBody>),
+        (
+            Entity,
+            &mut Bullet,
+            &mut Velocity,
+            &mut Lifetime,
+            &Hitbox,
+            &mut Position,
+        ),
+        Without<RigidBody>,
     >,
-    q_rigidbody: Query<(&Hitbox, &Transform), (With<RigidBody>, Without<Bullet>)>,
+    q_rigidbody: Query<(&Hitbox, &Position), (With<RigidBody>, Without<Bullet>)>,
 ) {
-    for (_b_entity, mut b_vel, b_hitbox, b_transform) in &mut q_bullet {
-        for (r_hitbox, r_transform) in &q_rigidbody {
-            // the arrow should bounce in this direction
-            let resolution = hitbox_collision((b_hitbox, b_transform), (r_hitbox, r_transform));
-            if resolution.x.abs() > 0. {
-                b_vel.0.x = b_vel.0.x.abs() * resolution.x.signum();
+    // each bullet only ever looks at itself and the terrain, so the order doesn't matter
+    for (entity, mut bullet, mut vel, mut lifetime, b_hitbox, mut b_pos) in &mut q_bullet {
+        if bullet.stuck {
+            continue;
+        }
+        let mut pos = b_pos.0;
+        for (r_hitbox, r_pos) in &q_rigidbody {
+            pos += hitbox_collision((b_hitbox, pos), (r_hitbox, r_pos.0));
+        }
+        let resolution = pos - b_pos.0;
+        if resolution == FixedVec2::ZERO {
+            continue;
+        }
+        match &arsenal.weapons[bullet.weapon as usize].on_impact {
+            Impact::Bounce { max_bounces } if bullet.bounces < *max_bounces => {
+                vel.0 = bounce(vel.0, resolution);
+                b_pos.0 = pos;
+                bullet.bounces += 1;
             }
-            if resolution.y.abs() > 0. {
-                b_vel.0.y = b_vel.0.y.abs() * resolution.y.signum();
+            Impact::Bounce { .. } => {
+                // spent, it drops at the foot of the wall
+                b_pos.0 = pos;
+                ammo::stop_arrow(&mut bullet, &mut vel, &mut lifetime, ammo::DROPPED_LINGER);
+            }
+            // left where it is, partly in the wall
+            Impact::Stick { linger } => {
+                ammo::stop_arrow(&mut bullet, &mut vel, &mut lifetime, *linger)
+            }
+            Impact::Break { sound } => {
+                commands.spawn(AudioBundle {
+                    source: asset_server.load(sound),
+                    settings: PlaybackSettings {
+                        mode: PlaybackMode::Despawn,
+                        volume: Volume::Relative(VolumeLevel::new(0.2)),
+                        speed: 3.,
+                        ..default()
+                    },
+                });
+                commands.entity(entity).despawn();
             }
         }
     }
 }
 
+/// a velocity glancing off a wall that pushed back by `resolution`
+fn bounce(velocity: FixedVec2, resolution: FixedVec2) -> FixedVec2 {
+    let mut velocity = velocity;
+    if resolution.x != Fixed::ZERO {
+        velocity.x = velocity.x.abs() * sign(resolution.x);
+    }
+    if resolution.y != Fixed::ZERO {
+        velocity.y = velocity.y.abs() * sign(resolution.y);
+    }
+    velocity
+}
+
 /// stop players from running into solid terrain
 pub fn player_terrain_system(
-    mut q_player: Query<(&Hitbox, &mut Transform), (With<Player>, Without<RigidBody>)>,
-    q_rigidbody: Query<(&Hitbox, &Transform), (With<RigidBody>, Without<Player>)>,
+    mut q_player: Query<(&Hitbox, &mut Position), (With<Player>, Without<RigidBody>)>,
+    q_rigidbody: Query<(&Hitbox, &Position), (With<RigidBody>, Without<Player>)>,
 ) {
-    for (p_hitbox, mut p_transform) in &mut q_player {
-        for (r_hitbox, r_transform) in &q_rigidbody {
-            let resolution = hitbox_collision((p_hitbox, &p_transform), (r_hitbox, &r_transform));
-            p_transform.translation.x += resolution.x;
-            p_transform.translation.y += resolution.y;
+    for (p_hitbox, mut p_pos) in &mut q_player {
+        for (r_hitbox, r_pos) in &q_rigidbody {
+            let resolution = hitbox_collision((p_hitbox, p_pos.0), (r_hitbox, r_pos.0));
+            p_pos.0 += resolution;
         }
     }
 }
@@ -165,29 +231,204 @@ impl Plugin for DebugHitboxPlugin {
     }
 }
 
-fn collision_debug_draw(mut gizmos: Gizmos, q_hitbox: Query<(&Hitbox, &Transform)>) {
-    for (hitbox, transform) in &q_hitbox {
-        match hitbox.with_transform(transform) {
+fn collision_debug_draw(mut gizmos: Gizmos, q_hitbox: Query<(&Hitbox, &Position)>) {
+    for (hitbox, pos) in &q_hitbox {
+        match hitbox.at(pos.0) {
             Hitbox::Circle { offset, radius } => {
-                gizmos.circle_2d(offset, radius, Color::RED);
+                gizmos.circle_2d(offset.to_vec2(), radius.to_f32(), Color::RED);
             }
             Hitbox::Rect { offset, half_size } => {
-                gizmos.rect_2d(offset, 0., half_size * 2., Color::RED);
+                gizmos.rect_2d(offset.to_vec2(), 0., half_size.to_vec2() * 2., Color::RED);
             }
         }
     }
 }
 
+/// -1 or 1, zero counting as positive
+fn sign(v: Fixed) -> Fixed {
+    if v < Fixed::ZERO {
+        -Fixed::ONE
+    } else {
+        Fixed::ONE
+    }
+}
+
+/// how far to push a box with the given center offset and overlap out along its shallowest axis
+fn push_out_of_box(delta: FixedVec2, overlap: FixedVec2) -> FixedVec2 {
+    if overlap.x <= Fixed::ZERO || overlap.y <= Fixed::ZERO {
+        FixedVec2::ZERO
+    } else if overlap.x < overlap.y {
+        FixedVec2::new(overlap.x * sign(delta.x), Fixed::ZERO)
+    } else {
+        FixedVec2::new(Fixed::ZERO, overlap.y * sign(delta.y))
+    }
+}
+
 /// returns the translation required to shift the dynamic body to not overlap with the rigid body
-pub fn hitbox_collision(dynamic: (&Hitbox, &Transform), rigid: (&Hitbox, &Transform)) -> Vec2 {
-    let shape_dynamic = dynamic.0.with_transform(dynamic.1).into_sepax();
-    let shape_rigid = rigid.0.with_transform(rigid.1).into_sepax();
-    Vec2::from(sat_collision(shape_rigid.as_ref(), shape_dynamic.as_ref()))
+pub fn hitbox_collision(dynamic: (&Hitbox, FixedVec2), rigid: (&Hitbox, FixedVec2)) -> FixedVec2 {
+    match (dynamic.0.at(dynamic.1), rigid.0.at(rigid.1)) {
+        (
+            Hitbox::Rect {
+                offset: a,
+                half_size: a_half,
+            },
+            Hitbox::Rect {
+                offset: b,
+                half_size: b_half,
+            },
+        ) => {
+            let delta = a - b;
+            let overlap = FixedVec2::new(
+                a_half.x + b_half.x - delta.x.abs(),
+                a_half.y + b_half.y - delta.y.abs(),
+            );
+            push_out_of_box(delta, overlap)
+        }
+        (
+            Hitbox::Circle {
+                offset: a,
+                radius: a_radius,
+            },
+            Hitbox::Circle {
+                offset: b,
+                radius: b_radius,
+            },
+        ) => {
+            let delta = a - b;
+            let dist = delta.length();
+            let depth = a_radius + b_radius - dist;
+            if depth <= Fixed::ZERO {
+                FixedVec2::ZERO
+            } else if dist == Fixed::ZERO {
+                // right on top of each other, any way out will do
+                FixedVec2::new(Fixed::ZERO, depth)
+            } else {
+                delta.normalize_or_zero() * depth
+            }
+        }
+        (
+            Hitbox::Circle { offset, radius },
+            Hitbox::Rect {
+                offset: b,
+                half_size,
+            },
+        ) => {
+            let closest = offset.clamp(b - half_size, b + half_size);
+            let delta = offset - closest;
+            if delta == FixedVec2::ZERO {
+                // the center is inside the box
+                let delta = offset - b;
+                let overlap = FixedVec2::new(
+                    half_size.x + radius - delta.x.abs(),
+                    half_size.y + radius - delta.y.abs(),
+                );
+                return push_out_of_box(delta, overlap);
+            }
+            let depth = radius - delta.length();
+            if depth <= Fixed::ZERO {
+                FixedVec2::ZERO
+            } else {
+                delta.normalize_or_zero() * depth
+            }
+        }
+        (rect @ Hitbox::Rect { .. }, circle @ Hitbox::Circle { .. }) => {
+            // the same as pushing the circle out of the box, the other way around
+            -hitbox_collision((&circle, FixedVec2::ZERO), (&rect, FixedVec2::ZERO))
+        }
+    }
+}
+
+/// check if two placed hitboxes intersect
+pub fn hitbox_intersects(a: (&Hitbox, FixedVec2), b: (&Hitbox, FixedVec2)) -> bool {
+    match (a.0.at(a.1), b.0.at(b.1)) {
+        (
+            Hitbox::Rect {
+                offset: a,
+                half_size: a_half,
+            },
+            Hitbox::Rect {
+                offset: b,
+                half_size: b_half,
+            },
+        ) => {
+            let delta = a - b;
+            delta.x.abs() < a_half.x + b_half.x && delta.y.abs() < a_half.y + b_half.y
+        }
+        (
+            Hitbox::Circle {
+                offset: a,
+                radius: a_radius,
+            },
+            Hitbox::Circle {
+                offset: b,
+                radius: b_radius,
+            },
+        ) => {
+            let reach = a_radius + b_radius;
+            a.distance_squared(b) < reach * reach
+        }
+        (
+            Hitbox::Circle { offset, radius },
+            Hitbox::Rect {
+                offset: b,
+                half_size,
+            },
+        )
+        | (
+            Hitbox::Rect {
+                offset: b,
+                half_size,
+            },
+            Hitbox::Circle { offset, radius },
+        ) => {
+            let closest = offset.clamp(b - half_size, b + half_size);
+            offset.distance_squared(closest) < radius * radius
+        }
+    }
+}
+
+#[test]
+fn boxes_are_pushed_out_the_shortest_way() {
+    let tile = Hitbox::Rect {
+        offset: FixedVec2::ZERO,
+        half_size: FixedVec2::splat(Fixed::from_int(8)),
+    };
+    let player = Hitbox::Rect {
+        offset: FixedVec2::ZERO,
+        half_size: FixedVec2::splat(Fixed::from_int(4)),
+    };
+    // overlapping the tile's right edge by one pixel
+    let pos = FixedVec2::new(Fixed::from_int(11), Fixed::from_int(2));
+    assert!(hitbox_intersects((&player, pos), (&tile, FixedVec2::ZERO)));
+    assert_eq!(
+        hitbox_collision((&player, pos), (&tile, FixedVec2::ZERO)),
+        FixedVec2::new(Fixed::ONE, Fixed::ZERO)
+    );
+    let arrow = Hitbox::Circle {
+        offset: FixedVec2::ZERO,
+        radius: Fixed::from_int(2),
+    };
+    let pos = FixedVec2::new(Fixed::from_int(12), Fixed::ZERO);
+    assert!(!hitbox_intersects((&arrow, pos), (&tile, FixedVec2::ZERO)));
+    assert_eq!(
+        hitbox_collision((&arrow, pos), (&tile, FixedVec2::ZERO)),
+        FixedVec2::ZERO
+    );
 }
 
-/// check if two transformed hitboxes intersect
-pub fn hitbox_intersects(a: (&Hitbox, &Transform), b: (&Hitbox, &Transform)) -> bool {
-    let shape_a = a.0.with_transform(a.1).into_sepax();
-    let shape_b = b.0.with_transform(b.1).into_sepax();
-    sat_overlap(shape_a.as_ref(), shape_b.as_ref())
+#[test]
+fn arrows_glance_off_walls() {
+    let velocity = FixedVec2::new(Fixed::from_int(2), -Fixed::ONE);
+    // hitting a floor only turns the fall around
+    let floor = FixedVec2::new(Fixed::ZERO, Fixed::from_ratio(1, 2));
+    assert_eq!(
+        bounce(velocity, floor),
+        FixedVec2::new(Fixed::from_int(2), Fixed::ONE)
+    );
+    // and a wall to the right sends it back left
+    let wall = FixedVec2::new(-Fixed::ONE, Fixed::ZERO);
+    assert_eq!(
+        bounce(velocity, wall),
+        FixedVec2::new(-Fixed::from_int(2), -Fixed::ONE)
+    );
 }
@@ -1,19 +1,47 @@
 use crate::{
+    ammo::Quiver,
     animation::*,
     collision::{Hitbox, WallSensors},
+    fixed::{Fixed, FixedVec2},
+    health::SinceHit,
+    weapon::Equipped,
+    BULLET_Z, PLAYER_Z,
 };
 use bevy::{prelude::*, sprite::Anchor};
+use std::f32::consts::PI;
 
 #[derive(Component, Debug)]
 pub struct Player {
     pub id: usize,
 }
 
+/// sprite tint of each player, indexed by ggrs handle
+pub const PLAYER_COLORS: [Color; 8] = [
+    Color::WHITE,
+    Color::rgb(1.0, 0.55, 0.55),
+    Color::rgb(0.55, 0.7, 1.0),
+    Color::rgb(0.6, 1.0, 0.6),
+    Color::rgb(1.0, 0.9, 0.45),
+    Color::rgb(0.9, 0.6, 1.0),
+    Color::rgb(0.5, 1.0, 0.95),
+    Color::rgb(1.0, 0.7, 0.35),
+];
+
+pub fn player_color(id: usize) -> Color {
+    PLAYER_COLORS[id % PLAYER_COLORS.len()]
+}
+
+/// the weapon a player holds, whichever one it is
 #[derive(Component, Debug)]
 pub struct Bow;
 
-#[derive(Component, Clone, Copy, Reflect, Default, Debug)]
-pub struct Velocity(pub Vec2);
+/// where something is in the simulation. its `Transform` only follows along for rendering
+#[derive(Component, Clone, Copy, Reflect, Default, Debug, Hash)]
+pub struct Position(pub FixedVec2);
+
+/// distance moved per frame
+#[derive(Component, Clone, Copy, Reflect, Default, Debug, Hash)]
+pub struct Velocity(pub FixedVec2);
 
 #[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Facing {
@@ -23,27 +51,36 @@ pub enum Facing {
     Left,
 }
 
-#[derive(Component, Clone, Copy, Reflect, Default, Debug)]
+#[derive(Component, Clone, Copy, Reflect, Default, Debug, Hash)]
 pub struct Lifetime(pub usize);
 
-#[derive(Component, Clone, Copy, Reflect, Default, Debug)]
+#[derive(Component, Clone, Copy, Reflect, Default, Debug, Hash)]
 pub struct InputAngle(pub u8);
 
-#[derive(Component, Clone, Copy, Reflect, Default, Debug)]
+#[derive(Component, Clone, Copy, Reflect, Default, Debug, Hash)]
 pub struct CanShoot {
     pub value: bool,
     pub since_last: usize,
+    /// frames fire has been held for a charged shot, which goes off on letting go
+    pub charge: usize,
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy, Default, Debug, Reflect, Hash)]
 pub struct Bullet {
     pub shot_by: usize,
+    /// the arsenal's weapon this came out of
+    pub weapon: u8,
+    pub damage: i32,
+    /// times it glanced off a wall
+    pub bounces: u8,
+    /// stuck in a wall, where it no longer hurts anyone
+    pub stuck: bool,
 }
 
-#[derive(Component, Clone, Copy, Default, Debug, Reflect)]
+#[derive(Component, Clone, Copy, Default, Debug, Reflect, Hash)]
 pub struct Health(pub i32);
 
-#[derive(Component, Clone, Copy, Default, Debug, Reflect)]
+#[derive(Component, Clone, Copy, Default, Debug, Reflect, Hash)]
 pub struct LastDamagedBy {
     pub id: usize,
 }
@@ -57,10 +94,14 @@ pub struct MainCamera;
 #[derive(Component)]
 pub struct MinimapCamera;
 
-#[derive(Component, Clone, Copy, Reflect, Debug, Default)]
+#[derive(Component, Clone, Copy, Reflect, Debug, Default, Hash)]
 pub struct Points(pub u32);
 
-#[derive(Component, Clone, Copy, Default, Debug, Reflect)]
+/// rounds won this match
+#[derive(Component, Clone, Copy, Reflect, Debug, Default, Hash)]
+pub struct RoundWins(pub u32);
+
+#[derive(Component, Clone, Copy, Default, Debug, Reflect, Hash)]
 pub struct WallContactState {
     pub up: bool,
     pub down: bool,
@@ -71,9 +112,18 @@ pub struct WallContactState {
 #[derive(Component, Default, Debug, Reflect)]
 pub struct Spawnpoint;
 
+/// where an arrow is drawn, pointing the way it flies
+pub fn bullet_transform(pos: FixedVec2, velocity: FixedVec2) -> Transform {
+    let angle = crate::input::vec_to_angle(velocity.to_vec2());
+    // the sprite points up and right
+    Transform::from_translation(pos.to_vec2().extend(BULLET_Z))
+        .with_rotation(Quat::from_rotation_z(2. * PI - angle + PI / 4.))
+}
+
 #[derive(Bundle)]
 pub struct BulletBundle {
     bullet: Bullet,
+    position: Position,
     velocity: Velocity,
     sprite: SpriteBundle,
     lifetime: Lifetime,
@@ -82,23 +132,25 @@ pub struct BulletBundle {
 
 impl BulletBundle {
     pub fn new(
-        shot_by: usize,
-        dir: Vec2,
-        vel: f32,
+        bullet: Bullet,
+        pos: FixedVec2,
+        velocity: FixedVec2,
         lifetime: usize,
         texture: Handle<Image>,
     ) -> Self {
         Self {
-            bullet: Bullet { shot_by },
-            velocity: Velocity(dir.normalize_or_zero() * vel),
+            bullet,
+            position: Position(pos),
+            velocity: Velocity(velocity),
             sprite: SpriteBundle {
                 texture,
+                transform: bullet_transform(pos, velocity),
                 ..default()
             },
             lifetime: Lifetime(lifetime),
             hitbox: Hitbox::Circle {
-                offset: Vec2::splat(3.),
-                radius: 2.5,
+                offset: FixedVec2::splat(Fixed::from_int(3)),
+                radius: Fixed::from_ratio(5, 2),
             },
         }
     }
@@ -112,20 +164,25 @@ pub struct BasePlayerBundle {
     can_shoot: CanShoot,
     wall_contact_state: WallContactState,
     health: Health,
+    since_hit: SinceHit,
     input_angle: InputAngle,
+    quiver: Quiver,
 }
 
-impl Default for BasePlayerBundle {
-    fn default() -> Self {
+impl BasePlayerBundle {
+    pub fn new(max_health: i32) -> Self {
         Self {
-            velocity: Velocity(Vec2::ZERO),
+            velocity: Velocity(FixedVec2::ZERO),
             can_shoot: CanShoot {
                 value: true,
                 since_last: 999,
+                charge: 0,
             },
             wall_contact_state: WallContactState::default(),
-            health: Health(1),
+            health: Health(max_health),
+            since_hit: SinceHit::default(),
             input_angle: InputAngle(0),
+            quiver: Quiver::default(),
         }
     }
 }
@@ -133,6 +190,7 @@ impl Default for BasePlayerBundle {
 #[derive(Bundle)]
 pub struct PlayerBundle {
     player: Player,
+    position: Position,
     base: BasePlayerBundle,
     sprite: SpriteSheetBundle,
     facing: Facing,
@@ -140,18 +198,29 @@ pub struct PlayerBundle {
     animation: AnimationBundle,
     wall_sensors: WallSensors,
     points: Points,
+    round_wins: RoundWins,
+    equipped: Equipped,
 }
 
 impl PlayerBundle {
-    pub fn new(id: usize, atlas: Handle<TextureAtlas>) -> Self {
-        const SIZE: f32 = 4.1;
-        const E: f32 = 0.05;
+    pub fn new(id: usize, pos: FixedVec2, max_health: i32, atlas: Handle<TextureAtlas>) -> Self {
+        const SIZE: Fixed = Fixed::from_ratio(41, 10);
+        const E: Fixed = Fixed::from_ratio(1, 20);
+        let rect = |x: Fixed, y: Fixed, half_x: Fixed, half_y: Fixed| Hitbox::Rect {
+            offset: FixedVec2::new(x, y),
+            half_size: FixedVec2::new(half_x, half_y),
+        };
         Self {
-            base: BasePlayerBundle::default(),
+            base: BasePlayerBundle::new(max_health),
             player: Player { id },
+            position: Position(pos),
             sprite: SpriteSheetBundle {
                 texture_atlas: atlas,
-                sprite: TextureAtlasSprite::new(0),
+                sprite: TextureAtlasSprite {
+                    color: player_color(id),
+                    ..TextureAtlasSprite::new(0)
+                },
+                transform: Transform::from_translation(pos.to_vec2().extend(PLAYER_Z)),
                 ..default()
             },
             facing: Facing::Down,
@@ -159,29 +228,16 @@ impl PlayerBundle {
                 PlayerAnimation::Stand(Facing::Down).into(),
                 Timer::from_seconds(0.125, TimerMode::Repeating),
             ),
-            hitbox: Hitbox::Rect {
-                offset: Vec2::ZERO,
-                half_size: Vec2::splat(SIZE),
-            },
+            hitbox: rect(Fixed::ZERO, Fixed::ZERO, SIZE, SIZE),
             wall_sensors: WallSensors {
-                up: Hitbox::Rect {
-                    offset: Vec2::Y * SIZE,
-                    half_size: Vec2::new(SIZE - E, E),
-                },
-                down: Hitbox::Rect {
-                    offset: Vec2::NEG_Y * SIZE,
-                    half_size: Vec2::new(SIZE - E, E),
-                },
-                left: Hitbox::Rect {
-                    offset: Vec2::NEG_X * SIZE,
-                    half_size: Vec2::new(E, SIZE - E),
-                },
-                right: Hitbox::Rect {
-                    offset: Vec2::X * SIZE,
-                    half_size: Vec2::new(E, SIZE - E),
-                },
+                up: rect(Fixed::ZERO, SIZE, SIZE - E, E),
+                down: rect(Fixed::ZERO, -SIZE, SIZE - E, E),
+                left: rect(-SIZE, Fixed::ZERO, E, SIZE - E),
+                right: rect(SIZE, Fixed::ZERO, E, SIZE - E),
             },
             points: Points(0),
+            round_wins: RoundWins(0),
+            equipped: Equipped::default(),
         }
     }
 }
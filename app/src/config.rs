@@ -0,0 +1,92 @@
+use bevy::{prelude::*, utils::HashMap};
+use std::str::FromStr;
+
+/// options given to the game at launch.
+/// on native these are `--key value` cli args (or `P2PG_KEY` env vars), on wasm they are url query parameters
+#[derive(Resource, Default, Debug, Clone)]
+pub struct LaunchOptions(HashMap<String, String>);
+
+impl LaunchOptions {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> Self {
+        let mut options = HashMap::new();
+        for (key, value) in std::env::vars() {
+            if let Some(key) = key.strip_prefix("P2PG_") {
+                options.insert(key.to_lowercase(), value);
+            }
+        }
+        // args take priority over the environment
+        options.extend(Self::parse_args(std::env::args().skip(1)).0);
+        Self(options)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn load() -> Self {
+        use wasm_bindgen::JsCast;
+
+        let mut options = HashMap::new();
+        let Some(search) = web_sys::window().and_then(|w| w.location().search().ok()) else {
+            return Self(options);
+        };
+        let Ok(params) = web_sys::UrlSearchParams::new_with_str(&search) else {
+            return Self(options);
+        };
+        let Ok(Some(entries)) = js_sys::try_iter(&params) else {
+            return Self(options);
+        };
+        for entry in entries.flatten() {
+            let pair: js_sys::Array = entry.unchecked_into();
+            if let (Some(key), Some(value)) = (pair.get(0).as_string(), pair.get(1).as_string()) {
+                options.insert(key, value);
+            }
+        }
+        Self(options)
+    }
+
+    /// parse `--key value`, `--key=value` and bare `--flag` arguments
+    pub fn parse_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut options = HashMap::new();
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            let Some(arg) = arg.strip_prefix("--") else {
+                warn!("ignoring unknown argument {arg}");
+                continue;
+            };
+            if let Some((key, value)) = arg.split_once('=') {
+                options.insert(key.to_owned(), value.to_owned());
+                continue;
+            }
+            let value = match args.peek() {
+                Some(next) if !next.starts_with("--") => args.next().unwrap(),
+                _ => "true".to_owned(),
+            };
+            options.insert(arg.to_owned(), value);
+        }
+        Self(options)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    /// fetch and parse an option, warning if it is present but malformed
+    pub fn parse<T: FromStr>(&self, key: &str) -> Option<T> {
+        let value = self.get(key)?;
+        let parsed = value.parse().ok();
+        if parsed.is_none() {
+            warn!("could not parse launch option {key}={value}");
+        }
+        parsed
+    }
+}
+
+#[test]
+fn parses_args() {
+    let options = LaunchOptions::parse_args(
+        ["--host=ws://localhost:3536", "--players", "3", "--debug"].map(String::from),
+    );
+    assert_eq!(options.get("host"), Some("ws://localhost:3536"));
+    assert_eq!(options.parse::<usize>("players"), Some(3));
+    assert_eq!(options.get("debug"), Some("true"));
+    assert_eq!(options.get("room"), None);
+}
@@ -0,0 +1,131 @@
+use bevy::prelude::*;
+use bevy_ggrs::{ggrs, Session};
+
+use crate::{
+    input::{InputDevice, LocalDevices},
+    p2p::{GgrsConfig, NetworkConfig, MAX_PLAYERS, MIN_PLAYERS},
+    rand::Seed,
+    GameState,
+};
+
+/// a screen gets crowded quickly, and four is as many pads as most people own
+pub const MAX_LOCAL_PLAYERS: usize = 4;
+
+/// players gathering on this machine for a local match, one device each
+#[derive(Resource, Default, Debug)]
+pub struct LocalMatchSetup {
+    pub devices: Vec<InputDevice>,
+    /// start the match next frame
+    pub requested: bool,
+}
+
+impl LocalMatchSetup {
+    /// a single player needs at least one bot to play against
+    pub fn can_start(&self, bots: usize) -> bool {
+        !self.devices.is_empty()
+            && (MIN_PLAYERS..=MAX_PLAYERS).contains(&(self.devices.len() + bots))
+    }
+
+    /// adds the device as the next player, unless it already plays or the match is full
+    pub fn join(&mut self, device: InputDevice) {
+        if !self.devices.contains(&device) && self.devices.len() < MAX_LOCAL_PLAYERS {
+            self.devices.push(device);
+        }
+    }
+}
+
+pub struct CouchPlugin;
+impl Plugin for CouchPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LocalMatchSetup>()
+            .add_systems(
+                Update,
+                (
+                    join_with_gamepads,
+                    start_local_match.run_if(|setup: Res<LocalMatchSetup>| setup.requested),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Lobby)),
+            )
+            .add_systems(
+                OnEnter(GameState::Game),
+                start_local_session
+                    .before(crate::spawn_players)
+                    .run_if(resource_exists::<LocalDevices>()),
+            );
+    }
+}
+
+/// south (a on xbox pads) joins, east leaves
+fn join_with_gamepads(
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    mut setup: ResMut<LocalMatchSetup>,
+) {
+    for gamepad in gamepads.iter() {
+        let device = InputDevice::Gamepad(gamepad);
+        if buttons.just_pressed(GamepadButton::new(gamepad, GamepadButtonType::South)) {
+            setup.join(device);
+        }
+        if buttons.just_pressed(GamepadButton::new(gamepad, GamepadButtonType::East)) {
+            setup.devices.retain(|d| *d != device);
+        }
+    }
+    // unplugged pads can't play
+    setup.devices.retain(|d| match d {
+        InputDevice::Gamepad(gamepad) => gamepads.contains(*gamepad),
+        InputDevice::KeyboardMouse => true,
+    });
+}
+
+fn start_local_match(
+    mut commands: Commands,
+    mut setup: ResMut<LocalMatchSetup>,
+    config: Res<NetworkConfig>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    setup.requested = false;
+    if !setup.can_start(config.bots.count) {
+        return;
+    }
+    commands.insert_resource(LocalDevices(setup.devices.clone()));
+    next_state.set(GameState::Game);
+}
+
+/// every player is local, so there is nothing to connect to and nothing to roll back.
+/// like any session, it starts on entering the game so the players are there for its first frame
+fn start_local_session(world: &mut World) {
+    let players = world.resource::<LocalDevices>().0.len();
+    let bots = world.resource::<NetworkConfig>().bots;
+    info!(
+        "starting local match with {players} players and {} bots",
+        bots.count
+    );
+    let session = ggrs::SessionBuilder::<GgrsConfig>::new()
+        .with_num_players(players + bots.count)
+        .with_check_distance(0)
+        .start_synctest_session()
+        .expect("Could not start local session.");
+    world.insert_resource(Session::SyncTest(session));
+    world.insert_resource(bots);
+    let format = world.resource::<NetworkConfig>().format;
+    world.insert_resource(format);
+    let health = world.resource::<NetworkConfig>().health;
+    world.insert_resource(health);
+    let nonce = world.resource::<Time<Real>>().elapsed().as_nanos() as u64;
+    world.insert_resource(Seed::local(nonce));
+}
+
+#[test]
+fn devices_join_once_up_to_the_limit() {
+    let mut setup = LocalMatchSetup::default();
+    setup.join(InputDevice::KeyboardMouse);
+    setup.join(InputDevice::KeyboardMouse);
+    assert!(!setup.can_start(0));
+    assert!(setup.can_start(1));
+    for id in 0..MAX_LOCAL_PLAYERS {
+        setup.join(InputDevice::Gamepad(Gamepad::new(id)));
+    }
+    assert_eq!(setup.devices.len(), MAX_LOCAL_PLAYERS);
+    assert!(setup.can_start(0));
+}
@@ -0,0 +1,140 @@
+use std::{collections::VecDeque, fmt::Debug};
+
+use bevy::prelude::*;
+use bevy_ggrs::{
+    ggrs::Frame, GgrsSchedule, Rollback, RollbackFrameCount, RollbackOrdered, Session,
+};
+use bevy_matchbox::prelude::PeerId;
+
+use crate::{
+    ammo::Quiver, bot::BotBrain, component::*, health::SinceHit, p2p::GgrsConfig, rand::Rng,
+    rounds::MatchState, weapon::Equipped, GameFrameCount,
+};
+
+/// simulated frames kept around in case a desync needs dumping, about three seconds
+const HISTORY_LEN: usize = 180;
+/// frames either side of the desynced one that go into a dump
+const DUMP_RADIUS: i32 = 30;
+
+/// a readable copy of the rollback state at the end of each recent frame
+#[derive(Resource, Default)]
+pub struct StateHistory(VecDeque<(Frame, String)>);
+
+/// set once peers disagree about the game state, the match is stopped from then on
+#[derive(Resource, Debug)]
+pub struct Desync {
+    pub frame: Frame,
+    pub peer: PeerId,
+    /// where the state dump ended up, if it could be written
+    pub dump: Option<String>,
+}
+
+pub struct DesyncPlugin;
+impl Plugin for DesyncPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StateHistory>().add_systems(
+            GgrsSchedule,
+            record_frame.in_set(crate::InspectFrame).run_if(
+                |session: Option<Res<Session<GgrsConfig>>>| {
+                    matches!(session.as_deref(), Some(Session::P2P(_)))
+                },
+            ),
+        );
+    }
+}
+
+fn record_frame(world: &mut World) {
+    let frame = i32::from(*world.resource::<RollbackFrameCount>());
+    let dump = dump_frame(world);
+    let mut history = world.resource_mut::<StateHistory>();
+    // resimulating a frame invalidates everything predicted after it
+    history.0.retain(|(f, _)| *f < frame);
+    history.0.push_back((frame, dump));
+    while history.0.len() > HISTORY_LEN {
+        history.0.pop_front();
+    }
+}
+
+/// one `#<rollback order> <component>` line per component, so dumps from different peers diff cleanly
+fn dump_components<T: Component + Debug>(world: &mut World, lines: &mut Vec<(usize, String)>) {
+    world.resource_scope(|world, order: Mut<RollbackOrdered>| {
+        for (rollback, component) in world.query::<(&Rollback, &T)>().iter(world) {
+            let order = order.order(*rollback);
+            lines.push((order, format!("  #{order} {component:?}")));
+        }
+    });
+}
+
+fn dump_frame(world: &mut World) -> String {
+    let mut lines = vec![];
+    dump_components::<Position>(world, &mut lines);
+    dump_components::<Velocity>(world, &mut lines);
+    dump_components::<Health>(world, &mut lines);
+    dump_components::<SinceHit>(world, &mut lines);
+    dump_components::<Points>(world, &mut lines);
+    dump_components::<RoundWins>(world, &mut lines);
+    dump_components::<CanShoot>(world, &mut lines);
+    dump_components::<Lifetime>(world, &mut lines);
+    dump_components::<InputAngle>(world, &mut lines);
+    dump_components::<WallContactState>(world, &mut lines);
+    dump_components::<LastDamagedBy>(world, &mut lines);
+    dump_components::<BotBrain>(world, &mut lines);
+    dump_components::<Equipped>(world, &mut lines);
+    dump_components::<Bullet>(world, &mut lines);
+    dump_components::<Quiver>(world, &mut lines);
+    // stable, so each entity's components stay in the order above
+    lines.sort_by_key(|(order, _)| *order);
+
+    let mut dump = format!(
+        "  {:?}\n  {:?}\n  {:?}\n",
+        world.resource::<Rng>(),
+        world.resource::<GameFrameCount>(),
+        world.resource::<MatchState>()
+    );
+    for (_, line) in lines {
+        dump.push_str(&line);
+        dump.push('\n');
+    }
+    dump
+}
+
+/// write the recorded frames around `frame` to disk, returning where they went
+pub fn write_dump(history: &StateHistory, frame: Frame, local_id: Option<usize>) -> Option<String> {
+    let mut text = String::new();
+    for (f, dump) in &history.0 {
+        if (*f - frame).abs() <= DUMP_RADIUS {
+            text.push_str(&format!("frame {f}\n{dump}\n"));
+        }
+    }
+    if text.is_empty() {
+        warn!("no recorded state around frame {frame} to dump");
+        return None;
+    }
+
+    let name = match local_id {
+        Some(id) => format!("desync-{frame}-player{}.txt", id + 1),
+        None => format!("desync-{frame}.txt"),
+    };
+    write_text(name, text)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_text(name: String, text: String) -> Option<String> {
+    match std::fs::write(&name, text) {
+        Ok(()) => {
+            info!("wrote state dump to {name}");
+            Some(name)
+        }
+        Err(e) => {
+            error!("could not write state dump {name}: {e}");
+            None
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_text(name: String, text: String) -> Option<String> {
+    // no filesystem in the browser, the console will have to do
+    info!("{name}\n{text}");
+    Some("the browser console".to_owned())
+}
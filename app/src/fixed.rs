@@ -0,0 +1,307 @@
+//! fixed-point numbers for the simulation. floats may round differently between native and wasm
+//! builds (and `sin`, `atan2` and friends certainly do), integers don't.
+
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+};
+
+use bevy::prelude::*;
+
+const FRAC_BITS: u32 = 16;
+
+/// a number with 16 fractional bits, plenty for pixels and speeds in pixels per frame
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Reflect)]
+pub struct Fixed(pub i64);
+
+impl Fixed {
+    pub const ZERO: Self = Self(0);
+    pub const ONE: Self = Self(1 << FRAC_BITS);
+    pub const PI: Self = Self(205_887);
+    pub const TAU: Self = Self(2 * Self::PI.0);
+    pub const FRAC_PI_2: Self = Self(Self::PI.0 / 2);
+
+    pub const fn from_int(i: i64) -> Self {
+        Self(i << FRAC_BITS)
+    }
+
+    /// `num / den`, for tuning constants
+    pub const fn from_ratio(num: i64, den: i64) -> Self {
+        Self((num << FRAC_BITS) / den)
+    }
+
+    /// only for values every peer loads the same way, i.e. from the map or the weapons file
+    pub fn from_f32(v: f32) -> Self {
+        Self((v as f64 * (1 << FRAC_BITS) as f64).round() as i64)
+    }
+
+    /// for rendering, never feed this back into the simulation
+    pub fn to_f32(self) -> f32 {
+        self.0 as f32 / (1 << FRAC_BITS) as f32
+    }
+
+    /// rounded to the nearest integer, halves away from zero
+    pub fn round(self) -> i64 {
+        let half = Self::ONE.0 / 2;
+        if self.0 < 0 {
+            -((-self.0 + half) >> FRAC_BITS)
+        } else {
+            (self.0 + half) >> FRAC_BITS
+        }
+    }
+
+    pub fn abs(self) -> Self {
+        Self(self.0.abs())
+    }
+
+    /// zero for anything that isn't positive
+    pub fn sqrt(self) -> Self {
+        if self.0 <= 0 {
+            return Self::ZERO;
+        }
+        Self(isqrt((self.0 as u128) << FRAC_BITS) as i64)
+    }
+
+    pub fn sin(self) -> Self {
+        // fold into [0, pi/2], where a taylor series is accurate to a few ulps
+        let mut x = self.0.rem_euclid(Self::TAU.0);
+        let negative = x >= Self::PI.0;
+        if negative {
+            x -= Self::PI.0;
+        }
+        if x > Self::FRAC_PI_2.0 {
+            x = Self::PI.0 - x;
+        }
+        let x = Self(x);
+        let x2 = x * x;
+        let mut sum = Self::ONE;
+        for n in [72, 42, 20, 6] {
+            sum = Self::ONE - x2 * sum / Self::from_int(n);
+        }
+        let sin = x * sum;
+        if negative {
+            -sin
+        } else {
+            sin
+        }
+    }
+
+    pub fn cos(self) -> Self {
+        (self + Self::FRAC_PI_2).sin()
+    }
+}
+
+/// the largest integer whose square is at most `n`
+fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    // start above the root, newton's method then only ever steps down towards it
+    let mut x = 1u128 << (128 - n.leading_zeros()).div_ceil(2);
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+impl fmt::Debug for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // exact, so dumps from different peers only differ where the state does
+        write!(f, "{}", self.0 as f64 / (1 << FRAC_BITS) as f64)
+    }
+}
+
+impl Add for Fixed {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, rhs: Self) {
+        self.0 += rhs.0;
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self(self.0 - rhs.0)
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self(-self.0)
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self(((self.0 as i128 * rhs.0 as i128) >> FRAC_BITS) as i64)
+    }
+}
+
+impl Div for Fixed {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        Self((((self.0 as i128) << FRAC_BITS) / rhs.0 as i128) as i64)
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Reflect)]
+pub struct FixedVec2 {
+    pub x: Fixed,
+    pub y: Fixed,
+}
+
+impl FixedVec2 {
+    pub const ZERO: Self = Self::new(Fixed::ZERO, Fixed::ZERO);
+
+    pub const fn new(x: Fixed, y: Fixed) -> Self {
+        Self { x, y }
+    }
+
+    pub const fn splat(v: Fixed) -> Self {
+        Self::new(v, v)
+    }
+
+    /// see `Fixed::from_f32`
+    pub fn from_vec2(v: Vec2) -> Self {
+        Self::new(Fixed::from_f32(v.x), Fixed::from_f32(v.y))
+    }
+
+    /// for rendering, never feed this back into the simulation
+    pub fn to_vec2(self) -> Vec2 {
+        Vec2::new(self.x.to_f32(), self.y.to_f32())
+    }
+
+    /// unit vector for an input angle, 0 - 255 clockwise from up like `PlayerInput::angle`
+    pub fn from_angle(angle: u8) -> Self {
+        let angle = Fixed(Fixed::TAU.0 * angle as i64 / 255);
+        Self::new(angle.sin(), angle.cos())
+    }
+
+    /// the input angle pointing closest to this direction
+    pub fn to_angle(self) -> u8 {
+        // 255 is a full turn, the same as 0
+        (0..255)
+            .max_by_key(|angle| Self::from_angle(*angle).dot(self))
+            .unwrap_or_default()
+    }
+
+    pub fn dot(self, rhs: Self) -> Fixed {
+        self.x * rhs.x + self.y * rhs.y
+    }
+
+    /// the z component of the cross product, positive if `rhs` is counterclockwise of `self`
+    pub fn perp_dot(self, rhs: Self) -> Fixed {
+        self.x * rhs.y - self.y * rhs.x
+    }
+
+    /// rotated 90 degrees counterclockwise
+    pub fn perp(self) -> Self {
+        Self::new(-self.y, self.x)
+    }
+
+    pub fn length_squared(self) -> Fixed {
+        self.dot(self)
+    }
+
+    pub fn length(self) -> Fixed {
+        self.length_squared().sqrt()
+    }
+
+    pub fn distance(self, rhs: Self) -> Fixed {
+        (self - rhs).length()
+    }
+
+    pub fn distance_squared(self, rhs: Self) -> Fixed {
+        (self - rhs).length_squared()
+    }
+
+    pub fn normalize_or_zero(self) -> Self {
+        let length = self.length();
+        if length == Fixed::ZERO {
+            return Self::ZERO;
+        }
+        Self::new(self.x / length, self.y / length)
+    }
+
+    pub fn clamp(self, min: Self, max: Self) -> Self {
+        Self::new(self.x.clamp(min.x, max.x), self.y.clamp(min.y, max.y))
+    }
+}
+
+impl fmt::Debug for FixedVec2 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({:?}, {:?})", self.x, self.y)
+    }
+}
+
+impl Add for FixedVec2 {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.x + rhs.x, self.y + rhs.y)
+    }
+}
+
+impl AddAssign for FixedVec2 {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for FixedVec2 {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.x - rhs.x, self.y - rhs.y)
+    }
+}
+
+impl SubAssign for FixedVec2 {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl Neg for FixedVec2 {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self::new(-self.x, -self.y)
+    }
+}
+
+impl Mul<Fixed> for FixedVec2 {
+    type Output = Self;
+    fn mul(self, rhs: Fixed) -> Self {
+        Self::new(self.x * rhs, self.y * rhs)
+    }
+}
+
+#[test]
+fn fixed_math_stays_close_to_floats() {
+    for angle in 0..=255u8 {
+        let fixed = FixedVec2::from_angle(angle).to_vec2();
+        let float: Vec2 = crate::input::from_u8_angle(angle).sin_cos().into();
+        assert!(
+            fixed.distance(float) < 0.001,
+            "angle {angle}: {fixed} vs {float}"
+        );
+    }
+    assert_eq!(FixedVec2::from_angle(64).to_angle(), 64);
+    assert!((Fixed::from_int(2).sqrt().to_f32() - 2f32.sqrt()).abs() < 0.0001);
+    assert_eq!((Fixed::from_int(-7) / Fixed::from_int(2)).round(), -4);
+}
@@ -1,20 +1,43 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, path::PathBuf};
 
 use crate::{
-    component::{Player, Points},
-    p2p::LocalPlayer,
-    GameState,
+    ammo::{Quiver, QUIVER_SIZE},
+    bot::{Bot, BotDifficulty},
+    camera::SpectatorCamera,
+    chat::{peer_name, send_chat, ChatLog, MAX_MESSAGE_LEN},
+    component::{player_color, Player, Points, RoundWins},
+    couch::{LocalMatchSetup, MAX_LOCAL_PLAYERS},
+    desync::Desync,
+    input::{GamepadConfig, InputDevice, LocalDevices, LEAVE, REMATCH},
+    p2p::{
+        sanitize_room, ConnectionStatus, GameSocket, GgrsConfig, InputDelay, LocalPlayer,
+        NetworkConfig, PeerRoles, Role, SimulatedFrames, MAX_INPUT_DELAY, MAX_PLAYERS,
+        MAX_PREDICTION, MIN_PLAYERS,
+    },
+    rand::{Rng, Seed},
+    replay::{list_replays, ReplayPlayback, WatchReplay, REPLAY_DIR},
+    rooms::RoomBrowser,
+    rounds::{LocalVote, MatchFormat, MatchState, Phase},
+    synctest::{SyncTestConfig, SyncTestReport, MAX_CHECK_DISTANCE},
+    weapon::{Arsenal, Equipped},
+    DebugState, GameState,
 };
 use bevy::prelude::*;
 use bevy_egui::{
-    egui::{self, Align2, Context, Pos2, Vec2},
+    egui::{self, Align2, Color32, Context, Pos2, Vec2},
     EguiContexts,
 };
+use bevy_ggrs::{ggrs::NetworkStats, LocalPlayers, RollbackFrameCount, Session};
 
 fn center_pos(ctx: &mut Context) -> Pos2 {
     (ctx.screen_rect().size() / 2.0).to_pos2()
 }
 
+fn egui_color(color: Color) -> Color32 {
+    let [r, g, b, _] = color.as_rgba_u8();
+    Color32::from_rgb(r, g, b)
+}
+
 pub fn fps_display(mut ctxs: EguiContexts, time: Res<Time>, mut history: Local<VecDeque<f32>>) {
     let ctx = ctxs.ctx_mut();
     let fps = (1. / time.delta_seconds()).round();
@@ -33,11 +56,102 @@ pub fn fps_display(mut ctxs: EguiContexts, time: Res<Time>, mut history: Local<V
         });
 }
 
+pub fn network_stats(
+    mut ctxs: EguiContexts,
+    session: Option<Res<Session<GgrsConfig>>>,
+    config: Res<NetworkConfig>,
+    simulated: Res<SimulatedFrames>,
+    frame: Res<RollbackFrameCount>,
+    seed: Res<Seed>,
+    input_delay: Option<Res<InputDelay>>,
+    time: Res<Time>,
+    mut samples: Local<VecDeque<(f32, u64, i32)>>,
+) {
+    let Some(session) = session else {
+        samples.clear();
+        return;
+    };
+
+    // anything simulated beyond the frames the game moved forward was resimulated
+    let now = time.elapsed_seconds();
+    let frame = i32::from(*frame);
+    samples.push_back((now, simulated.0, frame));
+    while samples.front().is_some_and(|(t, ..)| now - t > 1.) {
+        samples.pop_front();
+    }
+    let (then, simulated_then, frame_then) = samples[0];
+    let rollback_frames = (simulated.0 - simulated_then) as i64 - (frame - frame_then) as i64;
+    let rollback_fps = rollback_frames.max(0) as f32 / (now - then).max(1. / 60.);
+
+    let stats: Vec<(String, Option<NetworkStats>)> = match session.as_ref() {
+        Session::P2P(s) => s
+            .remote_player_handles()
+            .into_iter()
+            .map(|handle| {
+                (
+                    format!("Player {}", handle + 1),
+                    s.network_stats(handle).ok(),
+                )
+            })
+            .collect(),
+        Session::Spectator(s) => vec![("Host".to_owned(), s.network_stats().ok())],
+        Session::SyncTest(_) => vec![],
+    };
+
+    let ctx = ctxs.ctx_mut();
+    egui::Window::new("Network")
+        .anchor(Align2::RIGHT_TOP, Vec2::new(-72., 0.))
+        .resizable(false)
+        .collapsible(true)
+        .movable(false)
+        .show(ctx, |ui| {
+            egui::Grid::new("network_stats").show(ui, |ui| {
+                for header in ["", "Ping", "Queue", "kbps", "Behind"] {
+                    ui.strong(header);
+                }
+                ui.end_row();
+                for (name, stats) in stats {
+                    ui.label(name);
+                    match stats {
+                        Some(stats) => {
+                            ui.monospace(format!("{}ms", stats.ping));
+                            ui.monospace(stats.send_queue_len.to_string());
+                            ui.monospace(stats.kbps_sent.to_string());
+                            // how far behind we are, and how far behind they think they are
+                            ui.monospace(format!(
+                                "{}/{}",
+                                stats.local_frames_behind, stats.remote_frames_behind
+                            ));
+                        }
+                        None => {
+                            ui.weak("waiting...");
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+            ui.label(format!("Rollback frames/s: {}", rollback_fps.round()));
+            ui.label(format!("Seed: {}", seed.0));
+            if let (Session::P2P(_), Some(input_delay)) = (session.as_ref(), input_delay) {
+                let auto = if config.auto_input_delay {
+                    " (auto)"
+                } else {
+                    ""
+                };
+                ui.label(format!("Input delay: {} frames{auto}", input_delay.0));
+                ui.label(format!("Max prediction: {} frames", config.max_prediction));
+            }
+        });
+}
+
 pub fn points_display(
     mut ctxs: EguiContexts,
-    q_points: Query<(&Player, &Points)>,
-    local_player: Res<LocalPlayer>,
+    q_points: Query<(&Player, &Points, &RoundWins, Has<Bot>)>,
+    local_player: Option<Res<LocalPlayer>>,
+    format: Res<MatchFormat>,
 ) {
+    let local_id = local_player.map(|p| p.id);
+    let best_of = matches!(*format, MatchFormat::BestOf(_));
     let ctx = ctxs.ctx_mut();
     egui::Window::new("Points")
         .anchor(Align2::RIGHT_TOP, Vec2::ZERO)
@@ -45,32 +159,272 @@ pub fn points_display(
         .collapsible(true)
         .movable(false)
         .show(ctx, |ui| {
-            for (player, points) in &q_points {
+            ui.weak(format.to_string());
+            let mut scores: Vec<(&Player, &Points, &RoundWins, bool)> = q_points.iter().collect();
+            if best_of {
+                scores.sort_by_key(|(player, points, wins, _)| {
+                    (std::cmp::Reverse((wins.0, points.0)), player.id)
+                });
+            } else {
+                scores.sort_by_key(|(player, points, ..)| (std::cmp::Reverse(points.0), player.id));
+            }
+            for (player, points, wins, bot) in scores {
                 ui.horizontal(|ui| {
-                    if player.id == local_player.id {
-                        ui.label("You: ");
+                    let name = if Some(player.id) == local_id {
+                        "You: ".to_owned()
+                    } else if bot {
+                        format!("Bot {}: ", player.id + 1)
                     } else {
-                        ui.label("Them: ");
-                    }
+                        format!("Player {}: ", player.id + 1)
+                    };
+                    ui.colored_label(egui_color(player_color(player.id)), name);
                     ui.monospace(format!("{}", points.0));
+                    if best_of {
+                        ui.weak(format!("{} rounds", wins.0));
+                    }
                 });
             }
         });
 }
 
-pub fn main_menu(mut ctxs: EguiContexts, mut next_state: ResMut<NextState<GameState>>) {
+/// the weapons of the players on this machine, the one they hold highlighted
+pub fn weapon_display(
+    mut ctxs: EguiContexts,
+    q_player: Query<(&Player, &Equipped, &Quiver), Without<Bot>>,
+    local_players: Res<LocalPlayers>,
+    arsenal: Res<Arsenal>,
+) {
+    let mut locals: Vec<(&Player, &Equipped, &Quiver)> = q_player
+        .iter()
+        .filter(|(player, ..)| local_players.0.contains(&player.id))
+        .collect();
+    if locals.is_empty() {
+        return;
+    }
+    locals.sort_by_key(|(player, ..)| player.id);
+    let ctx = ctxs.ctx_mut();
+    egui::Window::new("Weapons")
+        .title_bar(false)
+        .anchor(Align2::LEFT_BOTTOM, Vec2::new(8., -8.))
+        .resizable(false)
+        .movable(false)
+        .show(ctx, |ui| {
+            for (player, equipped, quiver) in locals {
+                ui.horizontal(|ui| {
+                    if local_players.0.len() > 1 {
+                        ui.colored_label(
+                            egui_color(player_color(player.id)),
+                            format!("Player {}", player.id + 1),
+                        );
+                    }
+                    let arrows = format!("Arrows {}/{}", quiver.arrows, QUIVER_SIZE);
+                    if quiver.holds(arsenal.get(equipped)) {
+                        ui.label(arrows);
+                    } else {
+                        // not enough for a shot of what's in hand
+                        ui.colored_label(egui::Color32::LIGHT_RED, arrows);
+                    }
+                    for (i, weapon) in arsenal.weapons.iter().enumerate() {
+                        if i == equipped.weapon as usize {
+                            ui.strong(&weapon.name);
+                        } else {
+                            ui.weak(&weapon.name);
+                        }
+                    }
+                });
+            }
+            ui.weak("Q or the right bumper switches");
+        });
+}
+
+pub fn main_menu(
+    mut ctxs: EguiContexts,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut config: ResMut<NetworkConfig>,
+    mut synctest: ResMut<SyncTestConfig>,
+    mut local_match: ResMut<LocalMatchSetup>,
+    mut gamepad: ResMut<GamepadConfig>,
+    debug: Res<State<DebugState>>,
+    mut room_code: Local<String>,
+) {
     let ctx = ctxs.ctx_mut();
     egui::Window::new("Menu")
         .pivot(Align2::CENTER_CENTER)
         .default_pos(center_pos(ctx))
         .show(ctx, |ui| {
+            ui.add(
+                egui::Slider::new(&mut config.players, MIN_PLAYERS..=MAX_PLAYERS).text("Players"),
+            );
             if ui.button("Quick Play").clicked() {
+                config.room_code = None;
+                config.room_name = None;
+                config.password = None;
+                config.role = Role::Player;
                 next_state.set(GameState::Connecting);
             }
+            ui.separator();
+            // private rooms, for playing with friends
+            ui.horizontal(|ui| {
+                ui.label("Room code:");
+                ui.text_edit_singleline(&mut *room_code);
+            });
+            let code = sanitize_room(&room_code);
+            ui.horizontal(|ui| {
+                for (label, role) in [("Join Room", Role::Player), ("Spectate", Role::Spectator)] {
+                    if ui
+                        .add_enabled(!code.is_empty(), egui::Button::new(label))
+                        .clicked()
+                    {
+                        config.room_code = Some(code.clone());
+                        config.room_name = None;
+                        config.password = None;
+                        config.role = role;
+                        next_state.set(GameState::Connecting);
+                    }
+                }
+            });
+            ui.collapsing("Match", |ui| {
+                let mut best_of = matches!(config.format, MatchFormat::BestOf(_));
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut best_of, true, "Rounds");
+                    ui.selectable_value(&mut best_of, false, "Score limit");
+                });
+                match (&mut config.format, best_of) {
+                    (MatchFormat::BestOf(rounds), true) => {
+                        ui.add(egui::Slider::new(rounds, 1..=15).text("Best of"));
+                    }
+                    (MatchFormat::ScoreLimit(limit), false) => {
+                        ui.add(
+                            egui::Slider::new(limit, 100..=5000)
+                                .step_by(100.)
+                                .text("Points"),
+                        );
+                    }
+                    (format, true) => *format = MatchFormat::default(),
+                    (format, false) => *format = MatchFormat::ScoreLimit(1000),
+                }
+                let health = &mut config.health;
+                ui.add(egui::Slider::new(&mut health.max_health, 1..=10).text("Health"));
+                let mut regen = health.regen_after.is_some();
+                if ui.checkbox(&mut regen, "Regenerate health").changed() {
+                    health.regen_after = regen.then_some(5 * 60);
+                }
+                if let Some(after) = &mut health.regen_after {
+                    let mut secs = *after / 60;
+                    if ui
+                        .add(egui::Slider::new(&mut secs, 1..=30).text("Seconds without a hit"))
+                        .changed()
+                    {
+                        *after = secs * 60;
+                    }
+                }
+                ui.label("Online, the host picks the rules");
+            });
+            ui.collapsing("Bots", |ui| {
+                ui.add(egui::Slider::new(&mut config.bots.count, 0..=MAX_PLAYERS - 1).text("Bots"));
+                ui.horizontal(|ui| {
+                    for difficulty in BotDifficulty::ALL {
+                        ui.selectable_value(
+                            &mut config.bots.difficulty,
+                            difficulty,
+                            difficulty.to_string(),
+                        );
+                    }
+                });
+                ui.label("Online, the host's bots join the match");
+            });
+            ui.collapsing("Local match", |ui| {
+                ui.label("Press south (A) on a gamepad to join, east (B) to leave");
+                let mut leaving = None;
+                for (i, device) in local_match.devices.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.colored_label(
+                            egui_color(player_color(i)),
+                            format!("Player {}: {device}", i + 1),
+                        );
+                        if ui.small_button("Remove").clicked() {
+                            leaving = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = leaving {
+                    local_match.devices.remove(i);
+                }
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(
+                            !local_match.devices.contains(&InputDevice::KeyboardMouse)
+                                && local_match.devices.len() < MAX_LOCAL_PLAYERS,
+                            egui::Button::new("Add keyboard & mouse"),
+                        )
+                        .clicked()
+                    {
+                        local_match.join(InputDevice::KeyboardMouse);
+                    }
+                    if ui
+                        .add_enabled(
+                            local_match.can_start(config.bots.count),
+                            egui::Button::new("Start"),
+                        )
+                        .clicked()
+                    {
+                        local_match.requested = true;
+                    }
+                });
+            });
+            ui.collapsing("Netcode", |ui| {
+                ui.add_enabled(
+                    !config.auto_input_delay,
+                    egui::Slider::new(&mut config.input_delay, 0..=MAX_INPUT_DELAY)
+                        .text("Input delay"),
+                );
+                ui.checkbox(&mut config.auto_input_delay, "Pick input delay from ping");
+                ui.add(
+                    egui::Slider::new(&mut config.max_prediction, 1..=MAX_PREDICTION)
+                        .text("Max prediction"),
+                );
+            });
+            ui.collapsing("Gamepad", |ui| {
+                ui.add(
+                    egui::Slider::new(&mut gamepad.move_deadzone, 0.0..=0.95).text("Move deadzone"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut gamepad.aim_deadzone, 0.0..=0.95).text("Aim deadzone"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut gamepad.trigger_threshold, 0.0..=0.95)
+                        .text("Trigger threshold"),
+                );
+            });
+            if *debug.get() == DebugState::On {
+                ui.separator();
+                ui.collapsing("SyncTest", |ui| {
+                    ui.add(
+                        egui::Slider::new(&mut synctest.check_distance, 1..=MAX_CHECK_DISTANCE)
+                            .text("Check distance"),
+                    );
+                    ui.checkbox(&mut synctest.scripted, "Scripted inputs");
+                    if ui.button("Start SyncTest").clicked() {
+                        synctest.requested = true;
+                    }
+                });
+            }
         });
 }
 
-pub fn connecting(mut ctxs: EguiContexts) {
+pub fn connecting(
+    mut ctxs: EguiContexts,
+    config: Res<NetworkConfig>,
+    roles: Option<Res<PeerRoles>>,
+) {
+    // players we've heard from, plus ourselves
+    let connected = roles.map_or(0, |roles| {
+        roles
+            .0
+            .values()
+            .filter(|role| **role == Role::Player)
+            .count()
+    }) + 1;
     let ctx = ctxs.ctx_mut();
     egui::Window::new("Waiting for players...")
         .title_bar(false)
@@ -79,6 +433,502 @@ pub fn connecting(mut ctxs: EguiContexts) {
         .resizable(false)
         .collapsible(false)
         .show(ctx, |ui| {
-            ui.heading("Waiting for opponent...");
+            match config.role {
+                Role::Player => ui.heading(format!(
+                    "Waiting for players... ({}/{})",
+                    connected, config.players
+                )),
+                Role::Spectator => ui.heading("Waiting for the match to start..."),
+            };
+        });
+}
+
+pub fn chat_panel(
+    mut ctxs: EguiContexts,
+    socket: Option<ResMut<GameSocket>>,
+    mut log: ResMut<ChatLog>,
+    session: Option<Res<Session<GgrsConfig>>>,
+    roles: Option<Res<PeerRoles>>,
+    state: Res<State<GameState>>,
+    mut draft: Local<String>,
+) {
+    // outside of a room there's nobody to talk to, but the last match's chat can be read in the lobby
+    if socket.is_none() && (log.lines.is_empty() || *state.get() != GameState::Lobby) {
+        return;
+    }
+    let ctx = ctxs.ctx_mut();
+    egui::Window::new("Chat")
+        .anchor(Align2::LEFT_BOTTOM, Vec2::new(8., -8.))
+        .default_width(240.)
+        .resizable(false)
+        .collapsible(true)
+        .movable(false)
+        .show(ctx, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(160.)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    let lines = log
+                        .lines
+                        .iter()
+                        .filter(|line| !line.from.is_some_and(|peer| log.muted.contains(&peer)));
+                    for line in lines {
+                        ui.horizontal_wrapped(|ui| {
+                            ui.strong(format!("{}:", line.name));
+                            ui.label(&line.text);
+                        });
+                    }
+                });
+            let Some(mut socket) = socket else {
+                ui.weak("Not connected");
+                return;
+            };
+            ui.horizontal(|ui| {
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut *draft)
+                        .char_limit(MAX_MESSAGE_LEN)
+                        .hint_text("Say something"),
+                );
+                let entered =
+                    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                if entered || ui.button("Send").clicked() {
+                    send_chat(&mut socket, &mut log, &draft);
+                    draft.clear();
+                    // keep typing without clicking back into the box
+                    response.request_focus();
+                }
+            });
+            let peers: Vec<_> = socket.connected_peers().collect();
+            if !peers.is_empty() {
+                ui.collapsing("Mute", |ui| {
+                    for peer in peers {
+                        let mut muted = log.muted.contains(&peer);
+                        let name = peer_name(peer, session.as_deref(), roles.as_deref());
+                        if ui.checkbox(&mut muted, name).changed() {
+                            if muted {
+                                log.muted.insert(peer);
+                            } else {
+                                log.muted.remove(&peer);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+}
+
+pub fn spectating(
+    mut ctxs: EguiContexts,
+    camera: Res<SpectatorCamera>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let ctx = ctxs.ctx_mut();
+    egui::Window::new("Spectating")
+        .anchor(Align2::CENTER_BOTTOM, Vec2::new(0., -8.))
+        .title_bar(false)
+        .resizable(false)
+        .movable(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                match *camera {
+                    SpectatorCamera::Following(id) => ui.colored_label(
+                        egui_color(player_color(id)),
+                        format!("Watching player {}", id + 1),
+                    ),
+                    SpectatorCamera::Free => ui.label("Free camera"),
+                };
+                ui.weak("[Tab] next player, [Space] free camera");
+                if ui.button("Leave").clicked() {
+                    next_state.set(GameState::Lobby);
+                }
+            });
         });
 }
+
+pub fn synctest_display(
+    mut ctxs: EguiContexts,
+    config: Res<SyncTestConfig>,
+    report: Res<SyncTestReport>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let ctx = ctxs.ctx_mut();
+    egui::Window::new("SyncTest")
+        .anchor(Align2::LEFT_BOTTOM, Vec2::ZERO)
+        .resizable(false)
+        .collapsible(true)
+        .movable(false)
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "Check distance {}, {} inputs",
+                config.check_distance,
+                if config.scripted { "scripted" } else { "local" }
+            ));
+            ui.label(format!("Frames checked: {}", report.frames_checked));
+            if report.mismatches.is_empty() {
+                ui.colored_label(Color32::GREEN, "No mismatches");
+            } else {
+                ui.colored_label(
+                    Color32::RED,
+                    format!("{} mismatches", report.mismatches.len()),
+                );
+                // only the most recent ones, the full list is in the log
+                for mismatch in report.mismatches.iter().rev().take(8) {
+                    ui.monospace(format!(
+                        "frame {}: {}",
+                        mismatch.frame,
+                        mismatch.components.join(", ")
+                    ));
+                }
+            }
+            if ui.button("Leave").clicked() {
+                next_state.set(GameState::Lobby);
+            }
+        });
+}
+
+pub fn desync_display(
+    mut ctxs: EguiContexts,
+    desync: Res<Desync>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let ctx = ctxs.ctx_mut();
+    egui::Window::new("Desync")
+        .title_bar(false)
+        .pivot(Align2::CENTER_CENTER)
+        .default_pos(center_pos(ctx))
+        .resizable(false)
+        .collapsible(false)
+        .show(ctx, |ui| {
+            ui.heading("Out of sync!");
+            ui.label(format!(
+                "This game and {} disagree about frame {}, so the match has been stopped.",
+                desync.peer, desync.frame
+            ));
+            match &desync.dump {
+                Some(dump) => ui.label(format!(
+                    "The game state around it was saved to {dump}, compare it with the other player's to find the cause."
+                )),
+                None => ui.label("The game state could not be saved."),
+            };
+            if ui.button("Return to lobby").clicked() {
+                next_state.set(GameState::Lobby);
+            }
+        });
+}
+
+pub fn connection_status(
+    mut ctxs: EguiContexts,
+    status: Res<ConnectionStatus>,
+    time: Res<Time>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let ctx = ctxs.ctx_mut();
+    if status.forfeit {
+        egui::Window::new("Forfeit")
+            .title_bar(false)
+            .pivot(Align2::CENTER_CENTER)
+            .default_pos(center_pos(ctx))
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.heading("You win!");
+                ui.label("Everyone else left the match.");
+                if ui.button("Return to lobby").clicked() {
+                    next_state.set(GameState::Lobby);
+                }
+            });
+        return;
+    }
+    if status.interrupted.is_empty() {
+        return;
+    }
+    let mut interrupted: Vec<(&usize, &f32)> = status.interrupted.iter().collect();
+    interrupted.sort_by_key(|(id, _)| **id);
+    egui::Window::new("Connection unstable")
+        .anchor(Align2::CENTER_TOP, Vec2::new(0., 8.))
+        .title_bar(false)
+        .resizable(false)
+        .movable(false)
+        .show(ctx, |ui| {
+            ui.heading("Connection unstable");
+            for (id, deadline) in interrupted {
+                let left = (deadline - time.elapsed_seconds()).max(0.).ceil();
+                ui.colored_label(
+                    egui_color(player_color(*id)),
+                    format!("Waiting for player {}... {left}s", id + 1),
+                );
+            }
+        });
+}
+
+/// what's typed into the room browser
+#[derive(Default)]
+pub struct RoomForm {
+    name: String,
+    password: String,
+}
+
+pub fn room_browser(
+    mut ctxs: EguiContexts,
+    mut browser: ResMut<RoomBrowser>,
+    mut config: ResMut<NetworkConfig>,
+    mut next_state: ResMut<NextState<GameState>>,
+    time: Res<Time>,
+    mut form: Local<RoomForm>,
+) {
+    let ctx = ctxs.ctx_mut();
+    egui::Window::new("Rooms")
+        .anchor(Align2::RIGHT_TOP, Vec2::new(-8., 8.))
+        .resizable(false)
+        .show(ctx, |ui| {
+            if let Some(error) = &browser.error {
+                ui.colored_label(Color32::LIGHT_RED, error);
+            }
+            // only rooms of our game, which can be joined by their code
+            let rooms: Vec<_> = browser
+                .rooms
+                .iter()
+                .filter_map(|room| Some((room, config.code_of(&room.id)?.to_owned())))
+                .collect();
+            let mut join = None;
+            if rooms.is_empty() {
+                ui.weak("No open rooms");
+            } else {
+                egui::ScrollArea::vertical()
+                    .max_height(240.)
+                    .show(ui, |ui| {
+                        egui::Grid::new("rooms").show(ui, |ui| {
+                            for header in ["Name", "Map", "Players", ""] {
+                                ui.strong(header);
+                            }
+                            ui.end_row();
+                            for (room, code) in rooms {
+                                ui.label(&room.name);
+                                ui.label(room.map.as_deref().unwrap_or("?"));
+                                match room.capacity {
+                                    Some(cap) => ui.monospace(format!("{}/{cap}", room.players)),
+                                    None => ui.monospace(room.players.to_string()),
+                                };
+                                let label = if room.password { "Join 🔒" } else { "Join" };
+                                if ui
+                                    .add_enabled(!room.is_full(), egui::Button::new(label))
+                                    .clicked()
+                                {
+                                    join = Some((code, room.capacity, room.password));
+                                }
+                                ui.end_row();
+                            }
+                        });
+                    });
+            }
+            ui.horizontal(|ui| {
+                ui.label("Password:");
+                ui.add(egui::TextEdit::singleline(&mut form.password).password(true));
+            });
+            if ui
+                .add_enabled(!browser.fetching(), egui::Button::new("Refresh"))
+                .clicked()
+            {
+                browser.refresh = true;
+            }
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Room name:");
+                ui.text_edit_singleline(&mut form.name);
+            });
+            let name = form.name.trim().to_owned();
+            let create = ui
+                .add_enabled(
+                    !name.is_empty(),
+                    egui::Button::new(format!("Create {} player room", config.players)),
+                )
+                .clicked();
+
+            let password = Some(form.password.clone()).filter(|p| !p.is_empty());
+            if let Some((code, capacity, protected)) = join {
+                // the server groups peers by room and size, so both have to match
+                config.players = capacity.unwrap_or(config.players);
+                config.room_code = Some(code);
+                config.room_name = None;
+                config.password = password.filter(|_| protected);
+                config.role = Role::Player;
+                browser.error = None;
+                next_state.set(GameState::Connecting);
+            } else if create {
+                // anyone who can see the room can join it, the code only needs to be unique
+                let mut rng = Rng::new(time.elapsed().as_nanos() as u64 % 2_147_483_646 + 1);
+                let code = (0..6)
+                    .map(|_| {
+                        const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+                        CHARS[rng.next_usize(0, CHARS.len())] as char
+                    })
+                    .collect();
+                config.room_code = Some(code);
+                config.room_name = Some(name);
+                config.password = password;
+                config.role = Role::Player;
+                browser.error = None;
+                next_state.set(GameState::Connecting);
+            }
+        });
+}
+
+pub fn replay_browser(
+    mut commands: Commands,
+    mut ctxs: EguiContexts,
+    state: Res<State<GameState>>,
+    mut replays: Local<Option<Vec<PathBuf>>>,
+) {
+    // look again whenever we come back to the lobby, the last match may have been saved
+    if state.is_changed() {
+        *replays = None;
+    }
+    let replays = replays.get_or_insert_with(list_replays);
+    if replays.is_empty() {
+        return;
+    }
+    let ctx = ctxs.ctx_mut();
+    egui::Window::new("Watch replay")
+        .anchor(Align2::LEFT_TOP, Vec2::new(8., 8.))
+        .default_open(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(240.)
+                .show(ui, |ui| {
+                    for path in replays.iter() {
+                        let name = path.strip_prefix(REPLAY_DIR).unwrap_or(path);
+                        if ui.button(name.display().to_string()).clicked() {
+                            commands.insert_resource(WatchReplay(path.clone()));
+                        }
+                    }
+                });
+        });
+}
+
+pub fn replay_controls(
+    mut ctxs: EguiContexts,
+    playback: Res<ReplayPlayback>,
+    frame: Res<RollbackFrameCount>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    let ctx = ctxs.ctx_mut();
+    egui::Window::new("Replay")
+        .anchor(Align2::CENTER_BOTTOM, Vec2::new(0., -48.))
+        .title_bar(false)
+        .resizable(false)
+        .movable(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let total = playback.replay.frames.len();
+                let frame = (i32::from(*frame).max(0) as usize).min(total);
+                ui.monospace(format!("{frame}/{total}"));
+                if playback.finished {
+                    ui.label("Finished");
+                    return;
+                }
+                let label = if time.is_paused() { "Play" } else { "Pause" };
+                if ui.button(label).clicked() {
+                    if time.is_paused() {
+                        time.unpause();
+                    } else {
+                        time.pause();
+                    }
+                }
+                for (label, speed) in [("Slow", 0.5), ("Normal", 1.), ("Fast", 2.)] {
+                    if ui
+                        .selectable_label(time.relative_speed() == speed, label)
+                        .clicked()
+                    {
+                        time.set_relative_speed(speed);
+                    }
+                }
+            });
+        });
+}
+
+/// countdowns, round winners and the vote on what to do once the match is over
+pub fn match_status(
+    mut ctxs: EguiContexts,
+    state: Res<MatchState>,
+    mut vote: ResMut<LocalVote>,
+    q_player: Query<(&Player, Has<Bot>)>,
+    local_player: Option<Res<LocalPlayer>>,
+    devices: Option<Res<LocalDevices>>,
+    playback: Option<Res<ReplayPlayback>>,
+) {
+    let ctx = ctxs.ctx_mut();
+    let name = |id: Option<usize>| match id {
+        Some(id) if Some(id) == local_player.as_ref().map(|p| p.id) => "You".to_owned(),
+        Some(id) => format!("Player {}", id + 1),
+        None => "Nobody".to_owned(),
+    };
+    let color = |id: Option<usize>| id.map_or(Color32::GRAY, |id| egui_color(player_color(id)));
+    let banner = |ctx: &mut Context, id: &str, add: &mut dyn FnMut(&mut egui::Ui)| {
+        egui::Area::new(id.to_owned())
+            .anchor(Align2::CENTER_TOP, Vec2::new(0., 64.))
+            .interactable(false)
+            .show(ctx, |ui| ui.vertical_centered(|ui| add(ui)));
+    };
+    match state.phase {
+        Phase::Countdown => {
+            let seconds = state.frames_left.div_ceil(60);
+            banner(ctx, "Countdown", &mut |ui| {
+                ui.heading(format!("Round {}", state.rounds + 1));
+                ui.heading(seconds.to_string());
+            });
+        }
+        Phase::Live => {}
+        Phase::RoundEnd => banner(ctx, "Round over", &mut |ui| {
+            ui.heading(
+                egui::RichText::new(format!("{} won the round", name(state.winner)))
+                    .color(color(state.winner)),
+            );
+        }),
+        Phase::MatchEnd => {
+            // only players sharing this machine get a say
+            let voting = playback.is_none() && (local_player.is_some() || devices.is_some());
+            egui::Window::new("Match over")
+                .pivot(Align2::CENTER_CENTER)
+                .default_pos(center_pos(ctx))
+                .resizable(false)
+                .collapsible(false)
+                .show(ctx, |ui| {
+                    ui.heading(
+                        egui::RichText::new(match state.winner {
+                            Some(_) => format!("{} won the match", name(state.winner)),
+                            None => "It's a draw".to_owned(),
+                        })
+                        .color(color(state.winner)),
+                    );
+                    let mut players: Vec<(&Player, bool)> = q_player.iter().collect();
+                    players.sort_by_key(|(player, _)| player.id);
+                    for (player, bot) in players {
+                        let id = Some(player.id);
+                        let status = if bot || state.voted_rematch(player.id) {
+                            "wants a rematch"
+                        } else {
+                            "deciding"
+                        };
+                        ui.horizontal(|ui| {
+                            ui.colored_label(color(id), name(id));
+                            ui.weak(status);
+                        });
+                    }
+                    if state.leaving {
+                        ui.label("Someone left, back to the lobby...");
+                    } else if voting {
+                        ui.horizontal(|ui| {
+                            if ui.button("Rematch").clicked() {
+                                vote.0 |= REMATCH;
+                            }
+                            if ui.button("Return to lobby").clicked() {
+                                vote.0 |= LEAVE;
+                            }
+                        });
+                        ui.weak("Gamepads: start for a rematch, select to leave");
+                    }
+                });
+        }
+    }
+}
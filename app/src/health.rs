@@ -0,0 +1,180 @@
+use bevy::{prelude::*, sprite::Anchor};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    component::{Health, Player},
+    config::LaunchOptions,
+};
+
+/// frames between two points of health coming back
+const REGEN_INTERVAL: u32 = 60;
+/// width of a full health bar, a player is 16 pixels wide
+const BAR_WIDTH: f32 = 14.;
+
+pub struct HealthPlugin;
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HealthRules>()
+            .register_type::<SinceHit>();
+    }
+}
+
+/// how much players can take, and how they recover. every peer has to agree on this
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Reflect, Hash)]
+pub struct HealthRules {
+    pub max_health: i32,
+    /// frames after a hit in which nothing else hurts
+    pub invulnerable_frames: u32,
+    /// frames without getting hit before health starts coming back, if it ever does
+    pub regen_after: Option<u32>,
+}
+
+impl Default for HealthRules {
+    fn default() -> Self {
+        Self {
+            max_health: 3,
+            invulnerable_frames: 30,
+            regen_after: None,
+        }
+    }
+}
+
+impl HealthRules {
+    pub fn from_options(options: &LaunchOptions) -> Self {
+        let default = Self::default();
+        Self {
+            max_health: options
+                .parse("max-health")
+                .unwrap_or(default.max_health)
+                .max(1),
+            invulnerable_frames: default.invulnerable_frames,
+            regen_after: options
+                .parse("regen-after")
+                .map(|secs: f32| (secs.max(0.) * 60.) as u32),
+        }
+    }
+}
+
+/// frames since a player was last hurt
+#[derive(Component, Clone, Copy, Debug, Reflect, Hash)]
+pub struct SinceHit(pub u32);
+
+impl Default for SinceHit {
+    fn default() -> Self {
+        // long enough ago not to be invulnerable
+        Self(u32::MAX / 2)
+    }
+}
+
+impl SinceHit {
+    pub fn invulnerable(&self, rules: &HealthRules) -> bool {
+        self.0 < rules.invulnerable_frames
+    }
+}
+
+/// count the frames since everyone was last hit, and heal those who kept out of trouble long enough
+pub fn regenerate(rules: Res<HealthRules>, mut q_player: Query<(&mut Health, &mut SinceHit)>) {
+    for (mut health, mut since_hit) in &mut q_player {
+        since_hit.0 = since_hit.0.saturating_add(1);
+        let Some(after) = rules.regen_after else {
+            continue;
+        };
+        // the dead stay dead until the next round
+        if health.0 <= 0 || health.0 >= rules.max_health || since_hit.0 < after {
+            continue;
+        }
+        if (since_hit.0 - after) % REGEN_INTERVAL == 0 {
+            health.0 += 1;
+        }
+    }
+}
+
+/// the filled part of the bar above a player's head
+#[derive(Component)]
+pub struct HealthBar;
+
+/// the empty bar, to be spawned as a child of the player with `health_bar_fill` as its own child
+pub fn health_bar() -> SpriteBundle {
+    SpriteBundle {
+        sprite: Sprite {
+            color: Color::rgba(0., 0., 0., 0.6),
+            custom_size: Some(Vec2::new(BAR_WIDTH + 2., 4.)),
+            ..default()
+        },
+        // above the player, and over the map's foreground
+        transform: Transform::from_xyz(0., 12., 15.),
+        ..default()
+    }
+}
+
+pub fn health_bar_fill() -> (HealthBar, SpriteBundle) {
+    (
+        HealthBar,
+        SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgb(0.35, 0.85, 0.35),
+                custom_size: Some(Vec2::new(BAR_WIDTH, 2.)),
+                // shrinks towards the left
+                anchor: Anchor::CenterLeft,
+                ..default()
+            },
+            transform: Transform::from_xyz(-BAR_WIDTH / 2., 0., 1.),
+            ..default()
+        },
+    )
+}
+
+/// show how much health each player has left, and flash the bar while they're invulnerable
+pub fn update_health_bars(
+    rules: Res<HealthRules>,
+    q_player: Query<(&Health, &SinceHit), With<Player>>,
+    q_bar: Query<&Parent, Without<HealthBar>>,
+    mut q_fill: Query<(&mut Transform, &mut Sprite, &Parent), With<HealthBar>>,
+) {
+    for (mut transform, mut sprite, parent) in &mut q_fill {
+        let Ok(Ok((health, since_hit))) =
+            q_bar.get(parent.get()).map(|bar| q_player.get(bar.get()))
+        else {
+            continue;
+        };
+        let fraction = (health.0 as f32 / rules.max_health as f32).clamp(0., 1.);
+        transform.scale.x = fraction;
+        let color = if since_hit.invulnerable(&rules) && since_hit.0 / 4 % 2 == 0 {
+            Color::WHITE
+        } else if fraction > 0.34 {
+            Color::rgb(0.35, 0.85, 0.35)
+        } else {
+            Color::rgb(0.9, 0.3, 0.25)
+        };
+        if sprite.color != color {
+            sprite.color = color;
+        }
+    }
+}
+
+#[test]
+fn health_comes_back_after_a_while() {
+    let mut world = World::new();
+    world.insert_resource(HealthRules {
+        max_health: 3,
+        invulnerable_frames: 30,
+        regen_after: Some(120),
+    });
+    let player = world.spawn((Health(1), SinceHit(0))).id();
+    let mut schedule = Schedule::default();
+    schedule.add_systems(regenerate);
+    for _ in 0..119 {
+        schedule.run(&mut world);
+    }
+    assert_eq!(world.get::<Health>(player).unwrap().0, 1);
+    schedule.run(&mut world);
+    assert_eq!(world.get::<Health>(player).unwrap().0, 2);
+    for _ in 0..(REGEN_INTERVAL * 5) {
+        schedule.run(&mut world);
+    }
+    assert_eq!(world.get::<Health>(player).unwrap().0, 3);
+    assert!(!world
+        .get::<SinceHit>(player)
+        .unwrap()
+        .invulnerable(&HealthRules::default()));
+}
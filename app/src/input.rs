@@ -1,18 +1,23 @@
-use bevy::{prelude::*, utils::HashMap};
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
 use bevy_egui::EguiContexts;
 use bevy_ggrs::*;
 use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
 use std::f32::consts::PI;
 
 use crate::{
-    component::{InputAngle, Player},
+    bot::Bot,
+    component::{InputAngle, MainCamera, Player},
+    config::LaunchOptions,
+    fixed::FixedVec2,
     p2p::GgrsConfig,
+    rounds::LocalVote,
 };
 
 mod touch;
 pub use touch::*;
 
-#[derive(Clone, Copy, PartialEq, Zeroable, Pod, Debug, Default)]
+#[derive(Clone, Copy, PartialEq, Zeroable, Pod, Serialize, Deserialize, Debug, Default)]
 #[repr(C)]
 pub struct PlayerInput {
     pub dir: u8, // 0 - 255 clockwise from up
@@ -29,14 +34,23 @@ impl PlayerInput {
         self.btn & MOVE != 0
     }
 
-    pub fn direction(&self) -> Vec2 {
-        angle_to_vec(from_u8_angle(self.dir)).normalize_or_zero()
+    pub fn switch_weapon(&self) -> bool {
+        self.btn & SWITCH != 0
+    }
+
+    pub fn direction(&self) -> FixedVec2 {
+        FixedVec2::from_angle(self.dir)
     }
 }
 
 // button inputs
 pub const MOVE: u8 = 1 << 0; // any move input (usually wasd on pc and touch on mobile)
 pub const FIRE: u8 = 1 << 1;
+// votes once a match is over
+pub const REMATCH: u8 = 1 << 2;
+pub const LEAVE: u8 = 1 << 3;
+// cycles to the next weapon
+pub const SWITCH: u8 = 1 << 4;
 
 /// convert a 2d coordinate from view space to world space
 pub fn view_to_world(pos: Vec2, camera: &Camera, transform: &Transform) -> Vec2 {
@@ -55,8 +69,7 @@ pub fn to_u8_angle(angle: f32) -> u8 {
 // 0-255 => 0-2pi
 pub fn from_u8_angle(angle: u8) -> f32 {
     let t = (angle as f32) / 255.;
-    let angle = t.clamp(0., 1.) * 2. * PI;
-    angle
+    t.clamp(0., 1.) * 2. * PI
 }
 
 // angle clockwise from +Y from 0 to 2 PI
@@ -64,48 +77,133 @@ pub fn vec_to_angle(dir: Vec2) -> f32 {
     dir.angle_between(Vec2::NEG_Y) + PI
 }
 
-// angle is 0-2pi, returns unit vec clockwise from +y
-pub fn angle_to_vec(angle: f32) -> Vec2 {
-    angle.sin_cos().into()
+/// what drives a local player
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputDevice {
+    /// keyboard and mouse, or touch
+    KeyboardMouse,
+    Gamepad(Gamepad),
+}
+
+impl std::fmt::Display for InputDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::KeyboardMouse => write!(f, "Keyboard & mouse"),
+            Self::Gamepad(gamepad) => write!(f, "Gamepad {}", gamepad.id + 1),
+        }
+    }
+}
+
+/// devices of the players sharing this machine, by handle. without it every local handle is
+/// played with whichever device was used last
+#[derive(Resource, Debug, Clone)]
+pub struct LocalDevices(pub Vec<InputDevice>);
+
+/// how far sticks and triggers have to move before they count, from 0 to 1
+#[derive(Resource, Debug, Clone)]
+pub struct GamepadConfig {
+    pub move_deadzone: f32,
+    /// larger than the move deadzone, as a stick springing back would otherwise swing the aim around
+    pub aim_deadzone: f32,
+    pub trigger_threshold: f32,
+}
+
+impl FromWorld for GamepadConfig {
+    fn from_world(world: &mut World) -> Self {
+        let options = world
+            .get_resource::<LaunchOptions>()
+            .cloned()
+            .unwrap_or_default();
+        let zone = |key, default: f32| options.parse(key).unwrap_or(default).clamp(0., 0.95);
+        Self {
+            move_deadzone: zone("move-deadzone", 0.2),
+            aim_deadzone: zone("aim-deadzone", 0.5),
+            trigger_threshold: zone("trigger-threshold", 0.3),
+        }
+    }
+}
+
+/// a stick position, or `None` while it rests inside the deadzone
+fn outside_deadzone(stick: Vec2, deadzone: f32) -> Option<Vec2> {
+    (stick.length() > deadzone).then_some(stick)
 }
 
 /// primary ggrs input system
 pub fn input(
     mut commands: Commands,
     local_players: Res<LocalPlayers>,
+    devices: Option<Res<LocalDevices>>,
     keys: Res<Input<KeyCode>>,
     mouse_buttons: Res<Input<MouseButton>>,
+    mut cursor_moved: EventReader<CursorMoved>,
+    pads: Pads,
+    mut last_used: Local<Option<InputDevice>>,
     mut touch: ResMut<TouchMovement>,
+    vote: Res<LocalVote>,
 
     q_window: Query<(Entity, &Window)>,
-    mut q_player: Query<(&Player, &mut InputAngle)>,
+    mut q_player: Query<(&Player, &Transform, &mut InputAngle), Without<Bot>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
 
     mut ctxs: EguiContexts,
 ) {
     let mut local_inputs = HashMap::new();
 
+    // without assigned devices, play with whatever was touched last
+    if let Some(gamepad) = pads.gamepads.iter().find(|gamepad| pads.active(*gamepad)) {
+        *last_used = Some(InputDevice::Gamepad(gamepad));
+    }
+    if keys.get_just_pressed().next().is_some()
+        || mouse_buttons.get_just_pressed().next().is_some()
+        || cursor_moved.read().count() > 0
+        || last_used.is_some_and(|device| match device {
+            InputDevice::Gamepad(gamepad) => !pads.gamepads.contains(gamepad),
+            InputDevice::KeyboardMouse => false,
+        })
+    {
+        *last_used = Some(InputDevice::KeyboardMouse);
+    }
+
     for handle in &local_players.0 {
         let (window_entity, window) = q_window.single();
 
         let ctx = ctxs.try_ctx_for_window_mut(window_entity);
-        let Some((_, mut input_angle)) =
-            q_player.iter_mut().filter(|(p, ..)| p.id == *handle).next()
+        // bots decide for themselves in the simulation
+        let Some((_, player_transform, mut input_angle)) =
+            q_player.iter_mut().find(|(p, ..)| p.id == *handle)
         else {
             local_inputs.insert(*handle, PlayerInput::default());
             continue;
         };
+        // votes are cast from the gui for everyone sharing this machine
+        let votes = vote.0;
+
+        let device = devices
+            .as_ref()
+            .map_or(*last_used, |devices| devices.0.get(*handle).copied())
+            .unwrap_or(InputDevice::KeyboardMouse);
+        if let InputDevice::Gamepad(gamepad) = device {
+            let mut input = pads.input(gamepad, input_angle.0);
+            input_angle.0 = input.angle;
+            input.btn |= votes;
+            local_inputs.insert(*handle, input);
+            continue;
+        }
 
         let window_size = Vec2::new(window.width(), window.height());
 
-        if let Some(touch_input) = touch.drain(input_angle.0, window_size) {
+        if let Some(mut touch_input) = touch.drain(input_angle.0, window_size) {
             input_angle.0 = touch_input.angle;
+            touch_input.btn |= votes;
             local_inputs.insert(*handle, touch_input);
             continue;
         }
 
-        let mut btn = 0u8;
+        let mut btn = votes;
         let mut dir = IVec2::ZERO;
-        if window.focused {
+        // typing into the chat shouldn't walk the player around
+        let typing = ctx.as_ref().is_some_and(|ctx| ctx.wants_keyboard_input());
+        if window.focused && !typing {
             if keys.pressed(KeyCode::A) {
                 dir += IVec2::NEG_X;
             }
@@ -118,6 +216,9 @@ pub fn input(
             if keys.pressed(KeyCode::S) {
                 dir += IVec2::NEG_Y;
             }
+            if keys.pressed(KeyCode::Q) {
+                btn |= SWITCH;
+            }
             if mouse_buttons.pressed(MouseButton::Left)
                 && !ctx.is_some_and(|v| v.is_pointer_over_area())
             {
@@ -134,9 +235,15 @@ pub fn input(
 
         // fetch our own player position
         if let Some(cursor_pos) = cursor_pos {
-            // cursor in window
-            let center = window_size / 2.;
-            let mut dir = cursor_pos - center;
+            // the camera isn't always centered on us, i.e. at the map edges or with several local players
+            let player_pos = q_camera
+                .get_single()
+                .ok()
+                .and_then(|(camera, camera_transform)| {
+                    camera.world_to_viewport(camera_transform, player_transform.translation)
+                })
+                .unwrap_or(window_size / 2.);
+            let mut dir = cursor_pos - player_pos;
             dir.y = -dir.y;
             angle = to_u8_angle(vec_to_angle(dir));
         }
@@ -153,3 +260,113 @@ pub fn input(
 
     commands.insert_resource(LocalInputs::<GgrsConfig>(local_inputs));
 }
+
+/// everything needed to read the connected gamepads
+#[derive(SystemParam)]
+pub struct Pads<'w> {
+    gamepads: Res<'w, Gamepads>,
+    axes: Res<'w, Axis<GamepadAxis>>,
+    buttons: Res<'w, Input<GamepadButton>>,
+    /// how far analog buttons, i.e. triggers, are pressed
+    triggers: Res<'w, Axis<GamepadButton>>,
+    config: Res<'w, GamepadConfig>,
+}
+
+impl Pads<'_> {
+    fn stick(&self, gamepad: Gamepad, x: GamepadAxisType, y: GamepadAxisType) -> Vec2 {
+        Vec2::new(
+            self.axes
+                .get(GamepadAxis::new(gamepad, x))
+                .unwrap_or_default(),
+            self.axes
+                .get(GamepadAxis::new(gamepad, y))
+                .unwrap_or_default(),
+        )
+    }
+
+    fn left_stick(&self, gamepad: Gamepad) -> Option<Vec2> {
+        let stick = self.stick(
+            gamepad,
+            GamepadAxisType::LeftStickX,
+            GamepadAxisType::LeftStickY,
+        );
+        outside_deadzone(stick, self.config.move_deadzone)
+    }
+
+    fn right_stick(&self, gamepad: Gamepad) -> Option<Vec2> {
+        let stick = self.stick(
+            gamepad,
+            GamepadAxisType::RightStickX,
+            GamepadAxisType::RightStickY,
+        );
+        outside_deadzone(stick, self.config.aim_deadzone)
+    }
+
+    fn trigger(&self, gamepad: Gamepad) -> bool {
+        let trigger = GamepadButton::new(gamepad, GamepadButtonType::RightTrigger2);
+        // pads with digital triggers only report presses
+        self.triggers.get(trigger).unwrap_or_default() > self.config.trigger_threshold
+            || self.buttons.pressed(trigger)
+    }
+
+    /// whether anything on the pad is being used
+    fn active(&self, gamepad: Gamepad) -> bool {
+        self.left_stick(gamepad).is_some()
+            || self.right_stick(gamepad).is_some()
+            || self.trigger(gamepad)
+            || self
+                .buttons
+                .get_just_pressed()
+                .any(|button| button.gamepad == gamepad)
+    }
+
+    /// left stick moves, right stick aims, the right trigger fires and the right bumper switches weapons,
+    /// start and select vote once a match is over.
+    /// `angle` is kept while the right stick is at rest
+    fn input(&self, gamepad: Gamepad, angle: u8) -> PlayerInput {
+        let mut btn = 0u8;
+        let mut dir = 0u8;
+        if let Some(left) = self.left_stick(gamepad) {
+            btn |= MOVE;
+            dir = to_u8_angle(vec_to_angle(left));
+        }
+        if self.trigger(gamepad) {
+            btn |= FIRE;
+        }
+        if self
+            .buttons
+            .pressed(GamepadButton::new(gamepad, GamepadButtonType::RightTrigger))
+        {
+            btn |= SWITCH;
+        }
+        // only looked at once a match is over
+        if self
+            .buttons
+            .pressed(GamepadButton::new(gamepad, GamepadButtonType::Start))
+        {
+            btn |= REMATCH;
+        }
+        if self
+            .buttons
+            .pressed(GamepadButton::new(gamepad, GamepadButtonType::Select))
+        {
+            btn |= LEAVE;
+        }
+        PlayerInput {
+            dir,
+            btn,
+            angle: self
+                .right_stick(gamepad)
+                .map_or(angle, |right| to_u8_angle(vec_to_angle(right))),
+        }
+    }
+}
+
+#[test]
+fn resting_sticks_are_ignored() {
+    assert_eq!(outside_deadzone(Vec2::new(0.1, -0.1), 0.2), None);
+    assert_eq!(
+        outside_deadzone(Vec2::new(0., 0.5), 0.2),
+        Some(Vec2::new(0., 0.5))
+    );
+}
@@ -0,0 +1,795 @@
+// bevy systems commonly take many, deeply generic parameters
+#![allow(clippy::type_complexity, clippy::too_many_arguments)]
+
+use std::f32::consts::PI;
+
+use bevy::{
+    asset::{LoadState, LoadedUntypedAsset},
+    audio::Volume,
+    audio::{PlaybackMode, VolumeLevel},
+    prelude::*,
+};
+use bevy_egui::EguiPlugin;
+use bevy_ggrs::*;
+use bevy_inspector_egui::quick::WorldInspectorPlugin;
+
+mod ammo;
+mod animation;
+mod bot;
+mod camera;
+mod chat;
+pub mod checksum;
+mod collision;
+pub mod component;
+pub mod config;
+mod couch;
+mod desync;
+pub mod fixed;
+mod gui;
+mod health;
+mod input;
+mod map;
+mod p2p;
+mod rand;
+pub mod replay;
+mod rooms;
+mod rounds;
+mod synctest;
+mod weapon;
+
+use ammo::Quiver;
+use animation::*;
+use collision::*;
+use component::*;
+use fixed::{Fixed, FixedVec2};
+use health::HealthRules;
+use input::*;
+use map::*;
+use p2p::*;
+use rand::{Rng, Seed};
+use weapon::{Arsenal, Equipped};
+
+use p2p::GgrsConfig;
+
+#[derive(States, Default, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum GameState {
+    #[default]
+    Loading,
+    Lobby,
+    Connecting,
+    Game,
+}
+
+#[derive(States, Default, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum DebugState {
+    On,
+    #[default]
+    Off,
+}
+
+#[derive(Resource)]
+struct LoadingAssets(Vec<Handle<LoadedUntypedAsset>>);
+
+#[derive(Resource, Clone, Copy, Debug, Reflect, Default, Hash)]
+pub struct GameFrameCount(pub u64);
+
+/// exclusive systems that look at the finished state of a frame, after the gameplay has run
+#[derive(SystemSet, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct InspectFrame;
+
+pub const MAP_Z: f32 = 0.;
+pub const PLAYER_Z: f32 = 10.;
+pub const BULLET_Z: f32 = 15.;
+pub const MAP_FG_Z: f32 = 20.;
+
+pub const MAP_PATH: &str = "snowy.tmx";
+
+/// distance a player walks per frame
+pub const PLAYER_SPEED: Fixed = Fixed::from_ratio(7, 5);
+
+/// the deterministic game: loading, connecting, and the rollback simulation itself.
+/// everything here also runs headless, see `ClientPlugin` for the window, input and gui
+pub struct GamePlugin;
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(LoadingAssets(vec![]))
+            .init_resource::<Seed>()
+            .register_type::<WallContactState>()
+            .register_type::<Position>()
+            .register_type::<Velocity>()
+            .register_type::<RoundWins>()
+            .register_type::<InputAngle>()
+            .register_type::<Bullet>()
+            .add_plugins(map::TiledPlugin)
+            .add_plugins(weapon::WeaponPlugin)
+            .add_plugins(health::HealthPlugin)
+            .add_plugins(ammo::AmmoPlugin)
+            .add_plugins(NetworkingPlugin)
+            .add_plugins(checksum::StateChecksumPlugin)
+            .add_plugins(synctest::SyncTestPlugin)
+            .add_plugins(desync::DesyncPlugin)
+            .add_plugins(replay::ReplayPlugin)
+            .add_plugins(rounds::RoundsPlugin)
+            .add_state::<GameState>()
+            // LOADING
+            .add_systems(OnEnter(GameState::Loading), load) // load essential assets
+            .add_systems(Update, check_load.run_if(in_state(GameState::Loading))) // transition state when assets loaded
+            // LOBBY
+            .add_systems(OnEnter(GameState::Lobby), unload_game)
+            // CONNECTING
+            .add_systems(OnEnter(GameState::Connecting), setup_socket)
+            .add_systems(
+                Update,
+                (
+                    redeem_ticket,
+                    (exchange_roles, wait_for_players)
+                        .chain()
+                        .run_if(resource_exists::<GameSocket>()),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Connecting)),
+            ) // "lobby" -> waits for other player(s) and then transitions to countdown
+            .add_systems(Update, answer_latecomers.run_if(in_state(GameState::Game)))
+            .add_systems(
+                OnEnter(GameState::Game),
+                (spawn_players, reset_rollback_state).chain(),
+            ) // spawn players once connected
+            // COMBAT
+            .add_systems(
+                GgrsSchedule,
+                (
+                    bot::bot_input,
+                    rounds::freeze_inputs,
+                    rounds::advance_match,
+                    first_frame_init, // runs only if frame_count is 0
+                    sense_walls,
+                    move_player,
+                    collision::player_terrain_system,
+                    track_player_facing,
+                    point_bow,
+                    weapon::switch_weapon,
+                    shoot,
+                    collision::bullet_terrain_system,
+                    collision::bullet_player_system,
+                    health::regenerate,
+                    (ammo::pick_up_arrows, ammo::resupply).chain(),
+                    award_points,
+                    reload,
+                    move_bullets,
+                    despawn_after_lifetime,
+                    increment_frame_count,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Game)),
+            ) // synchronized p2p combat system ("the gameplay")
+            .configure_sets(
+                GgrsSchedule,
+                // they only read the simulation, so don't mind running in any order
+                InspectFrame
+                    .after(increment_frame_count)
+                    .ambiguous_with(InspectFrame),
+            )
+            .add_systems(
+                Update,
+                (
+                    process_ggrs_events,
+                    restore_bullet_sprites.run_if(in_state(GameState::Game)),
+                    project_transforms,
+                ),
+            );
+    }
+}
+
+/// the window, local input, camera, audio-visual feedback and gui on top of `GamePlugin`
+pub struct ClientPlugin;
+impl Plugin for ClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<camera::SpectatorCamera>()
+            .init_resource::<GamepadConfig>()
+            .add_plugins(SpriteAnimationPlugin)
+            .add_plugins(DebugHitboxPlugin)
+            .add_plugins(TouchPlugin)
+            .add_plugins(rooms::RoomBrowserPlugin)
+            .add_plugins(chat::ChatPlugin)
+            .add_plugins(couch::CouchPlugin)
+            .add_state::<DebugState>()
+            .add_systems(
+                ReadInputs,
+                input::input.run_if(
+                    not(synctest::scripted_inputs)
+                        .and_then(not(resource_exists::<replay::ReplayPlayback>())),
+                ),
+            )
+            .add_systems(
+                OnExit(GameState::Loading),
+                (camera::spawn_primary, camera::spawn_minimap).chain(),
+            ) // pre-connect initialization (camera, bg, etc.)
+            .add_systems(
+                OnEnter(GameState::Lobby),
+                (camera::reset_spectator_camera, camera::reset_zoom),
+            )
+            .add_systems(
+                Update,
+                (gui::main_menu, gui::room_browser, gui::replay_browser)
+                    .run_if(in_state(GameState::Lobby)),
+            )
+            .add_systems(
+                Update,
+                gui::connecting
+                    .after(wait_for_players)
+                    .run_if(in_state(GameState::Connecting)),
+            )
+            // MISC
+            .add_systems(
+                Update,
+                (
+                    toggle_debug,
+                    camera::follow_player.after(project_transforms),
+                    camera::frame_local_players
+                        .after(project_transforms)
+                        .run_if(
+                            in_state(GameState::Game).and_then(resource_exists::<LocalDevices>()),
+                        ),
+                    (camera::spectator_camera_controls, gui::spectating).run_if(
+                        in_state(GameState::Game)
+                            .and_then(not(resource_exists::<LocalPlayer>()))
+                            .and_then(not(resource_exists::<LocalDevices>())),
+                    ),
+                    animate_player,
+                    (
+                        animate_bow,
+                        weapon::show_equipped_weapon,
+                        weapon::charge_sound,
+                        health::update_health_bars,
+                    )
+                        .run_if(in_state(GameState::Game)),
+                    gui::points_display.run_if(in_state(GameState::Game)),
+                    gui::weapon_display.run_if(
+                        in_state(GameState::Game)
+                            .and_then(resource_exists::<LocalPlayers>())
+                            .and_then(not(resource_exists::<replay::ReplayPlayback>())),
+                    ),
+                    gui::match_status.run_if(
+                        in_state(GameState::Game).and_then(resource_exists::<rounds::MatchState>()),
+                    ),
+                    gui::synctest_display.run_if(
+                        in_state(GameState::Game)
+                            .and_then(resource_exists::<synctest::SyncTestReport>()),
+                    ),
+                    gui::desync_display.run_if(resource_exists::<desync::Desync>()),
+                    gui::connection_status.run_if(in_state(GameState::Game)),
+                    gui::replay_controls.run_if(
+                        in_state(GameState::Game)
+                            .and_then(resource_exists::<replay::ReplayPlayback>()),
+                    ),
+                    gui::chat_panel.run_if(not(in_state(GameState::Loading))),
+                    gui::fps_display.run_if(in_state(DebugState::On)),
+                    gui::network_stats.run_if(in_state(DebugState::On)),
+                ),
+            ) // client-side non-deterministic systems
+            .add_plugins(EguiPlugin)
+            .add_plugins(WorldInspectorPlugin::new().run_if(in_state(DebugState::On)));
+    }
+}
+
+fn award_points(
+    mut commands: Commands,
+    q_damaged: Query<(Entity, &LastDamagedBy, &Health)>,
+    mut q_player: Query<(&Player, &mut Points)>,
+) {
+    for (victim, damager, health) in &q_damaged {
+        for (attacker, mut points) in &mut q_player {
+            if attacker.id == damager.id {
+                // this is the player that shot the damaging bullet, only the last hit scores
+                if health.0 <= 0 {
+                    points.0 += 100;
+                }
+                commands.entity(victim).remove::<LastDamagedBy>();
+            }
+        }
+    }
+}
+
+fn first_frame_init(
+    mut commands: Commands,
+    fc: Res<GameFrameCount>,
+    mut q_player: Query<(Entity, &mut Position), With<Player>>,
+    q_bullet: Query<Entity, With<Bullet>>,
+    q_spawns: Query<&Position, (With<Spawnpoint>, Without<Player>)>,
+    mut rng: ResMut<Rng>,
+    health: Res<HealthRules>,
+) {
+    if fc.0 != 0 {
+        return;
+    }
+
+    // fetch all map spawnpoints
+    let mut spawns: Vec<FixedVec2> = q_spawns.iter().map(|pos| pos.0).collect();
+    // the rng has to pick from the same list everywhere
+    spawns.sort_by_key(|pos| (pos.x, pos.y));
+
+    let player_iter = q_player.iter_mut();
+    assert!(spawns.len() >= player_iter.len());
+
+    // for every player...
+    for (player, mut pos) in player_iter {
+        // reset core components
+        commands
+            .entity(player)
+            .insert(BasePlayerBundle::new(health.max_health));
+
+        //.. move to a random spawn point
+        pos.0 = rng.extract_random(&mut spawns);
+    }
+
+    // despawn all bullets
+    for bullet in &q_bullet {
+        commands.entity(bullet).despawn_recursive();
+    }
+}
+
+/// arrows brought back by a rollback only get their rollback components back, so dress them up again
+fn restore_bullet_sprites(
+    mut commands: Commands,
+    q_bullet: Query<(Entity, &Bullet, &Position, &Velocity), Without<Sprite>>,
+    sprites: Res<weapon::WeaponSprites>,
+) {
+    for (entity, bullet, pos, vel) in &q_bullet {
+        commands.entity(entity).insert((
+            Sprite::default(),
+            sprites.projectiles[bullet.weapon as usize].clone(),
+            bullet_transform(pos.0, vel.0),
+            GlobalTransform::default(),
+            VisibilityBundle::default(),
+        ));
+    }
+}
+
+/// draw everything where the simulation put it
+fn project_transforms(
+    mut q_moving: Query<(&Position, &mut Transform, Option<&Velocity>, Has<Bullet>)>,
+) {
+    for (pos, mut transform, vel, bullet) in &mut q_moving {
+        let z = transform.translation.z;
+        transform.translation = pos.0.to_vec2().extend(z);
+        if let Some(vel) = vel.filter(|vel| bullet && vel.0 != FixedVec2::ZERO) {
+            transform.rotation = bullet_transform(pos.0, vel.0).rotation;
+        }
+    }
+}
+
+fn increment_frame_count(mut fc: ResMut<GameFrameCount>) {
+    fc.0 += 1;
+}
+
+fn toggle_debug(
+    state: Res<State<DebugState>>,
+    mut next_state: ResMut<NextState<DebugState>>,
+    keys: Res<Input<KeyCode>>,
+) {
+    if keys.just_pressed(KeyCode::Slash) {
+        next_state.set(match state.get() {
+            DebugState::Off => DebugState::On,
+            DebugState::On => DebugState::Off,
+        });
+    }
+}
+
+fn unload_game(
+    mut commands: Commands,
+    q_player: Query<Entity, With<Player>>,
+    q_bullet: Query<Entity, With<Bullet>>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    // remove all players and bullets
+    for e in &q_player {
+        commands.entity(e).despawn_recursive();
+    }
+    for e in &q_bullet {
+        commands.entity(e).despawn_recursive();
+    }
+    // the next match picks its own seed
+    commands.insert_resource(Seed::default());
+    // remove any sockets and sessions
+    commands.remove_resource::<GameSocket>();
+    commands.remove_resource::<PeerRoles>();
+    commands.remove_resource::<PeerSettings>();
+    commands.remove_resource::<SeedNonces>();
+    commands.remove_resource::<JoinedAt>();
+    commands.remove_resource::<PendingTicket>();
+    commands.remove_resource::<InputDelay>();
+    commands.remove_resource::<bot::BotSettings>();
+    commands.remove_resource::<Session<GgrsConfig>>();
+    commands.remove_resource::<LocalPlayer>();
+    commands.remove_resource::<LocalDevices>();
+    commands.remove_resource::<synctest::SyncTestReport>();
+    commands.remove_resource::<desync::Desync>();
+    commands.remove_resource::<rounds::MatchState>();
+    commands.insert_resource(ConnectionStatus::default());
+    commands.remove_resource::<replay::ReplayPlayback>();
+    // replays may have left the clock sped up or paused
+    time.set_relative_speed(1.);
+    time.unpause();
+    commands.insert_resource(desync::StateHistory::default());
+}
+
+fn load(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut loading: ResMut<LoadingAssets>,
+) {
+    [
+        "Archer.png",
+        "arrow.png",
+        "bow.png",
+        "sfx/Bow_Charge.wav",
+        "sfx/Bow_Release.wav",
+        "sfx/Damage_1.wav",
+        MAP_PATH,
+        weapon::WEAPONS_PATH,
+        "tilesets/Set_A_Darkwoods1.png",
+    ]
+    .into_iter()
+    .for_each(|asset| {
+        loading.0.push(asset_server.load_untyped(asset));
+    });
+
+    // load the tilemap
+    commands
+        .spawn(TilemapLoaderBundle::new(MAP_PATH))
+        .insert(Transform::from_translation(
+            (-16. * 25., -16. * 25., MAP_Z).into(),
+        ));
+}
+
+fn check_load(
+    loading: Res<LoadingAssets>,
+    loading_handles: Res<Assets<LoadedUntypedAsset>>,
+    asset_server: Res<AssetServer>,
+    mut _commands: Commands,
+    mut next_state: ResMut<NextState<GameState>>,
+    q_tilemap_loaders: Query<&TilemapLoader>,
+) {
+    let assets_loaded: bool = loading.0.iter().all(|lua_handle| {
+        loading_handles.get(lua_handle).is_some_and(|lua| {
+            match asset_server.get_load_state(lua.handle.id()) {
+                Some(LoadState::Loaded) => true,
+                Some(LoadState::Failed) => panic!("An asset failed to load."),
+                _ => false,
+            }
+        })
+    });
+    let tilemaps_loaded: bool = q_tilemap_loaders.is_empty();
+    if assets_loaded && tilemaps_loaded {
+        next_state.set(GameState::Lobby);
+    }
+}
+
+fn move_bullets(mut q_bullets: Query<(&Velocity, &mut Position), With<Bullet>>) {
+    for (vel, mut pos) in &mut q_bullets {
+        pos.0 += vel.0;
+    }
+}
+
+fn shoot(
+    mut commands: Commands,
+    inputs: Res<PlayerInputs<GgrsConfig>>,
+    mut q_player: Query<(&Player, &Position, &Equipped, &mut CanShoot, &mut Quiver)>,
+    arsenal: Res<Arsenal>,
+    sprites: Res<weapon::WeaponSprites>,
+    mut rng: ResMut<Rng>,
+    state: Res<rounds::MatchState>,
+    asset_server: Res<AssetServer>,
+) {
+    // the rng has to be drawn from in the same order everywhere
+    let mut players: Vec<_> = q_player.iter_mut().collect();
+    players.sort_by_key(|(player, ..)| player.id);
+
+    for (player, player_pos, equipped, mut can_shoot, mut quiver) in players {
+        let (input, _) = inputs[player.id];
+        let weapon = arsenal.get(equipped);
+        let ready =
+            can_shoot.value && can_shoot.since_last >= weapon.cooldown && quiver.holds(weapon);
+
+        let power = if state.phase != rounds::Phase::Live {
+            // fire is let go of for everyone once the round is over, that's no reason to shoot
+            if can_shoot.charge > 0 {
+                can_shoot.charge = 0;
+            }
+            continue;
+        } else if weapon.charge_frames == 0 {
+            if !(input.fire() && ready) {
+                continue;
+            }
+            Fixed::ONE
+        } else if input.fire() && (ready || can_shoot.charge > 0) {
+            can_shoot.charge = (can_shoot.charge + 1).min(weapon.charge_frames);
+            continue;
+        } else if can_shoot.charge > 0 {
+            // let go, even a quick tap gets an arrow out
+            weapon.power(std::mem::take(&mut can_shoot.charge))
+        } else {
+            continue;
+        };
+        can_shoot.value = false;
+        can_shoot.since_last = 0;
+        quiver.arrows -= weapon.projectiles;
+
+        // stronger shots fly faster, and so further, and hit harder
+        let damage = (Fixed::from_int(weapon.damage as i64) * power)
+            .round()
+            .max(1) as i32;
+        for angle in weapon.projectile_angles(input.angle, &mut rng) {
+            let dir = FixedVec2::from_angle(angle);
+            let pos = player_pos.0 + dir * Fixed::from_int(16);
+            commands
+                .spawn(BulletBundle::new(
+                    Bullet {
+                        shot_by: player.id,
+                        weapon: equipped.weapon,
+                        damage,
+                        ..default()
+                    },
+                    pos,
+                    dir * (weapon.speed * power),
+                    weapon.lifetime,
+                    sprites.projectiles[equipped.weapon as usize].clone(),
+                ))
+                .add_rollback();
+        }
+
+        commands.spawn(AudioBundle {
+            source: asset_server.load("sfx/Bow_Release.wav"),
+            settings: PlaybackSettings {
+                mode: PlaybackMode::Despawn,
+                volume: Volume::Relative(VolumeLevel::new(0.2)),
+                speed: 2.,
+                ..default()
+            },
+        });
+    }
+}
+
+fn reload(
+    mut _commands: Commands,
+    _asset_server: Res<AssetServer>,
+    inputs: Res<PlayerInputs<GgrsConfig>>,
+    mut q_player: Query<(&Player, &mut CanShoot)>,
+) {
+    for (player, mut can_shoot) in &mut q_player {
+        let (input, _) = inputs[player.id];
+
+        can_shoot.since_last += 1;
+
+        if !input.fire() && !can_shoot.value {
+            can_shoot.value = true;
+        }
+    }
+}
+
+fn point_bow(
+    q_player: Query<&Player>,
+    mut q_bow: Query<(&mut Transform, &Parent), With<Bow>>,
+    inputs: Res<PlayerInputs<GgrsConfig>>,
+) {
+    for (mut transform, parent) in &mut q_bow {
+        let Ok(player) = q_player.get(parent.get()) else {
+            continue;
+        };
+        let (input, _) = inputs[player.id];
+
+        let mut angle = 2. * PI - from_u8_angle(input.angle);
+        angle += PI / 4.;
+
+        transform.rotation = Quat::from_rotation_z(angle);
+    }
+}
+
+fn track_player_facing(
+    mut q_player: Query<(&Player, &mut Facing)>,
+    inputs: Res<PlayerInputs<GgrsConfig>>,
+) {
+    for (player, mut facing) in &mut q_player {
+        let (input, _) = inputs[player.id];
+
+        if input.angle < 32 {
+            *facing = Facing::Up;
+        } else if input.angle < 96 {
+            *facing = Facing::Right;
+        } else if input.angle < 160 {
+            *facing = Facing::Down;
+        } else if input.angle < 224 {
+            *facing = Facing::Left;
+        } else {
+            *facing = Facing::Up;
+        }
+    }
+}
+
+fn animate_bow(
+    q_player: Query<
+        (&CanShoot, &Equipped),
+        (With<Player>, Or<(Changed<CanShoot>, Changed<Equipped>)>),
+    >,
+    mut q_bow: Query<(&mut AnimationIndices, &Parent), With<Bow>>,
+    arsenal: Res<Arsenal>,
+) {
+    for (mut bow_indices, parent) in &mut q_bow {
+        let Ok((can_shoot, equipped)) = q_player.get(parent.get()) else {
+            continue;
+        };
+        let charge_frames = arsenal.get(equipped).charge_frames;
+        let new_indices = if can_shoot.charge > 0 {
+            BowAnimation::Charge(1 + can_shoot.charge * 2 / charge_frames)
+        } else if can_shoot.since_last <= 10 {
+            BowAnimation::Empty
+        } else if charge_frames > 0 {
+            // nocked, waiting to be drawn
+            BowAnimation::Charge(1)
+        } else {
+            BowAnimation::Draw
+        }
+        .into();
+        if *bow_indices != new_indices {
+            *bow_indices = new_indices;
+        }
+    }
+}
+
+fn animate_player(mut q_player: Query<(&Velocity, &Facing, &mut AnimationIndices), With<Player>>) {
+    for (velocity, facing, mut indices) in &mut q_player {
+        let new_indices = player_animation_indices(velocity.0.to_vec2(), facing);
+        if *indices != new_indices {
+            *indices = new_indices;
+        }
+    }
+}
+
+fn sense_walls(
+    mut q_player: Query<
+        (&WallSensors, &mut WallContactState, &Position),
+        (With<Player>, Without<RigidBody>),
+    >,
+    q_rigidbody: Query<(&Hitbox, &Position), (With<RigidBody>, Without<Player>)>,
+) {
+    for (p_wallsensors, mut walls, p_pos) in &mut q_player {
+        let p_pos = p_pos.0;
+        let mut hitting_up = false;
+        let mut hitting_down = false;
+        let mut hitting_right = false;
+        let mut hitting_left = false;
+        for (r_hitbox, r_pos) in &q_rigidbody {
+            let rigid = (r_hitbox, r_pos.0);
+            if !hitting_up {
+                hitting_up = hitbox_intersects((&p_wallsensors.up, p_pos), rigid);
+            }
+            if !hitting_down {
+                hitting_down = hitbox_intersects((&p_wallsensors.down, p_pos), rigid);
+            }
+            if !hitting_left {
+                hitting_left = hitbox_intersects((&p_wallsensors.left, p_pos), rigid);
+            }
+            if !hitting_right {
+                hitting_right = hitbox_intersects((&p_wallsensors.right, p_pos), rigid);
+            }
+        }
+        walls.up = hitting_up;
+        walls.down = hitting_down;
+        walls.left = hitting_left;
+        walls.right = hitting_right;
+    }
+}
+
+fn wall_direction_clamp(mut dir: FixedVec2, walls: &WallContactState) -> FixedVec2 {
+    if (walls.up && dir.y > Fixed::ZERO) || (walls.down && dir.y < Fixed::ZERO) {
+        dir.y = Fixed::ZERO;
+    }
+    if (walls.right && dir.x > Fixed::ZERO) || (walls.left && dir.x < Fixed::ZERO) {
+        dir.x = Fixed::ZERO;
+    }
+    dir
+}
+
+fn move_player(
+    mut q_player: Query<(&mut Position, &mut Velocity, &WallContactState, &Player)>,
+    inputs: Res<PlayerInputs<GgrsConfig>>,
+) {
+    // the epsilon around which input axis are snapped to 0
+    const SNAP_TO_AXIS: Fixed = Fixed::from_ratio(1, 50);
+    for (mut pos, mut velocity, walls, player) in &mut q_player {
+        let (input, _) = inputs[player.id];
+
+        velocity.0 = if !input.moving() {
+            FixedVec2::ZERO
+        } else {
+            let mut input_dir = input.direction();
+            if input_dir.x.abs() <= SNAP_TO_AXIS {
+                input_dir.x = Fixed::ZERO
+            }
+            if input_dir.y.abs() <= SNAP_TO_AXIS {
+                input_dir.y = Fixed::ZERO
+            }
+            wall_direction_clamp(input_dir, walls).normalize_or_zero() * PLAYER_SPEED
+        };
+
+        pos.0 += velocity.0;
+    }
+}
+
+fn despawn_after_lifetime(
+    mut commands: Commands,
+    mut query: Query<(
+        Entity,
+        &mut Lifetime,
+        Option<&mut Bullet>,
+        Option<&mut Velocity>,
+    )>,
+) {
+    for (entity, mut lifetime, bullet, vel) in &mut query {
+        lifetime.0 -= 1;
+        if lifetime.0 > 0 {
+            continue;
+        }
+        match (bullet, vel) {
+            // arrows that fall short land where they are, to be picked up again
+            (Some(mut bullet), Some(mut vel)) if !bullet.stuck => {
+                ammo::stop_arrow(&mut bullet, &mut vel, &mut lifetime, ammo::DROPPED_LINGER);
+            }
+            _ => commands.entity(entity).despawn_recursive(),
+        }
+    }
+}
+
+fn reset_rollback_state(mut commands: Commands, seed: Res<Seed>) {
+    commands.insert_resource(GameFrameCount(0));
+    commands.insert_resource(Rng::new(seed.0));
+    commands.insert_resource(rounds::MatchState::default());
+}
+
+fn spawn_players(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut atlases: ResMut<Assets<TextureAtlas>>,
+    session: Res<Session<GgrsConfig>>,
+    bots: Option<Res<bot::BotSettings>>,
+    weapon_sprites: Res<weapon::WeaponSprites>,
+    health: Res<HealthRules>,
+) {
+    let player_image = asset_server.load("Archer.png");
+    let player_atlas =
+        TextureAtlas::from_grid(player_image.clone(), Vec2::splat(16.), 24, 1, None, None);
+    let player_atlas_handle = atlases.add(player_atlas);
+
+    // everyone starts out with the first weapon
+    let bow_atlas_handle = weapon_sprites.held[0].clone();
+
+    // one player per ggrs handle, moved to a spawnpoint on the first frame
+    let num_players = session_num_players(&session);
+    let first_bot = num_players - bots.as_ref().map_or(0, |bots| bots.count);
+    for id in 0..num_players {
+        let pos = FixedVec2::new(Fixed::from_int(16 * id as i64), Fixed::ZERO);
+        let mut player = commands.spawn(PlayerBundle::new(
+            id,
+            pos,
+            health.max_health,
+            player_atlas_handle.clone(),
+        ));
+        player.with_children(|parent| {
+            parent
+                .spawn(BowBundle::new(bow_atlas_handle.clone()))
+                .add_rollback();
+            parent.spawn(health::health_bar()).with_children(|bar| {
+                bar.spawn(health::health_bar_fill());
+            });
+        });
+        if let Some(bots) = bots.as_ref().filter(|_| id >= first_bot) {
+            player.insert((
+                bot::Bot {
+                    difficulty: bots.difficulty,
+                },
+                bot::BotBrain::default(),
+            ));
+        }
+        player.add_rollback();
+    }
+}
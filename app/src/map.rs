@@ -3,7 +3,8 @@ use std::{io::Cursor, path::Path, sync::Arc, time::Duration};
 use crate::{
     animation::{AnimationBundle, AnimationIndices},
     collision::{Hitbox, RigidBodyBundle},
-    component::Spawnpoint,
+    component::{Position, Spawnpoint},
+    fixed::{Fixed, FixedVec2},
     MAP_FG_Z,
 };
 use anyhow::anyhow;
@@ -15,7 +16,6 @@ use bevy::{
     sprite::{MaterialMesh2dBundle, Mesh2dHandle},
     utils::BoxedFuture,
 };
-use tiled;
 
 pub struct TiledPlugin;
 impl Plugin for TiledPlugin {
@@ -181,7 +181,7 @@ fn tilemap_initializer(
         };
         let material_handle = materials.add(material);
 
-        let map_size = map_size(&map);
+        let map_size = map_size(map);
         let tileset_tile_size = UVec2::new(tileset.tile_width, tileset.tile_height).as_vec2();
 
         let map_tf = transform.cloned().unwrap_or_default();
@@ -189,7 +189,7 @@ fn tilemap_initializer(
         // parent tilemap entity
         commands.spawn(TilemapBundle {
             tilemap: Tilemap,
-            aabb: tilemap_aabb(&map),
+            aabb: tilemap_aabb(map),
             spatial: SpatialBundle::from_transform(map_tf),
         });
 
@@ -199,7 +199,7 @@ fn tilemap_initializer(
             let layer_z_offset = layer_i as f32 * 0.1;
             match layer.layer_type() {
                 tiled::LayerType::Tiles(layer) => {
-                    let (mesh, animated) = decompose_layer(&map, &layer, tileset.as_ref());
+                    let (mesh, animated) = decompose_layer(map, &layer, tileset.as_ref());
                     let mesh_handle = meshes.add(mesh);
 
                     let z = layer_z_offset
@@ -235,11 +235,10 @@ fn tilemap_initializer(
                 }
                 tiled::LayerType::Objects(layer) => {
                     if layer_type.is_some_and(|v| v == "collision") {
-                        let hitboxes = layer_to_collision(&map, &layer);
-                        hitboxes.into_iter().for_each(|(hitbox, transform)| {
-                            commands
-                                .spawn(RigidBodyBundle::new(hitbox))
-                                .insert(transform.mul_transform(map_tf));
+                        let hitboxes = layer_to_collision(map, &layer);
+                        hitboxes.into_iter().for_each(|(hitbox, center)| {
+                            let pos = map_tf.transform_point(center.extend(0.)).truncate();
+                            commands.spawn(RigidBodyBundle::new(hitbox, FixedVec2::from_vec2(pos)));
                         });
                     } else if layer_type.is_some_and(|v| v == "spawnpoints") {
                         layer
@@ -251,11 +250,8 @@ fn tilemap_initializer(
                                 _ => None,
                             })
                             .for_each(|spawnpoint| {
-                                commands
-                                    .spawn(Spawnpoint)
-                                    .insert(TransformBundle::from_transform(map_tf.mul_transform(
-                                        Transform::from_translation(spawnpoint.extend(0.)),
-                                    )));
+                                let pos = map_tf.transform_point(spawnpoint.extend(0.)).truncate();
+                                commands.spawn((Spawnpoint, Position(FixedVec2::from_vec2(pos))));
                             });
                     }
                 }
@@ -268,7 +264,8 @@ fn tilemap_initializer(
     }
 }
 
-fn layer_to_collision(map: &tiled::Map, layer: &tiled::ObjectLayer) -> Vec<(Hitbox, Transform)> {
+/// hitboxes and their centers, in map space
+fn layer_to_collision(map: &tiled::Map, layer: &tiled::ObjectLayer) -> Vec<(Hitbox, Vec2)> {
     let mut hitboxes = vec![];
 
     let map_size = map_size(map);
@@ -282,10 +279,10 @@ fn layer_to_collision(map: &tiled::Map, layer: &tiled::ObjectLayer) -> Vec<(Hitb
                 let center = Vec2::new(pos.x + size.x / 2., pos.y - size.y / 2.);
                 hitboxes.push((
                     Hitbox::Rect {
-                        offset: Vec2::ZERO,
-                        half_size: size / 2.,
+                        offset: FixedVec2::ZERO,
+                        half_size: FixedVec2::from_vec2(size / 2.),
                     },
-                    Transform::from_translation(center.extend(0.)),
+                    center,
                 ));
             }
             tiled::ObjectShape::Ellipse { width, height } => {
@@ -296,10 +293,10 @@ fn layer_to_collision(map: &tiled::Map, layer: &tiled::ObjectLayer) -> Vec<(Hitb
                 let center = Vec2::new(pos.x + radius, pos.y - radius);
                 hitboxes.push((
                     Hitbox::Circle {
-                        offset: Vec2::ZERO,
-                        radius,
+                        offset: FixedVec2::ZERO,
+                        radius: Fixed::from_f32(radius),
                     },
-                    Transform::from_translation(center.extend(0.)),
+                    center,
                 ));
             }
             _ => (),
@@ -327,7 +324,7 @@ fn map_size(map: &tiled::Map) -> Vec2 {
 }
 
 fn tile_to_uvs(tile: tiled::LayerTile, image_size: Vec2, tile_size: Vec2) -> [[f32; 2]; 4] {
-    let id = tile.id() as u32;
+    let id = tile.id();
 
     // columns in the tileset
     let columns = (image_size.x / tile_size.x).round() as u32;
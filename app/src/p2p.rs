@@ -1,9 +1,25 @@
-use bevy::prelude::*;
-use bevy_ggrs::*;
-use bevy_matchbox::prelude::*;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use crate::{component::*, GameFrameCount};
-use crate::{input, rand::Rng, GameState};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_ggrs::{ggrs::GgrsEvent, *};
+use bevy_matchbox::{matchbox_socket::Packet, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bot::{BotBrain, BotSettings},
+    desync::{self, Desync, StateHistory},
+    health::{HealthRules, SinceHit},
+    input,
+    rand::{Rng, Seed},
+    rooms::{spawn_request, RoomBrowser},
+    rounds::{MatchFormat, MatchState},
+    GameState, MAP_PATH,
+};
+use crate::{component::*, config::LaunchOptions, GameFrameCount};
 
 #[derive(Debug)]
 pub struct GgrsConfig;
@@ -18,98 +34,845 @@ pub struct LocalPlayer {
     pub id: usize,
 }
 
+/// the unreliable channel owned by ggrs
+pub const GGRS_CHANNEL: usize = 0;
+/// a reliable channel for messages outside of rollback
+pub const LOBBY_CHANNEL: usize = 1;
+/// a reliable channel for text chat, see `chat`
+pub const CHAT_CHANNEL: usize = 2;
+
+pub type GameSocket = MatchboxSocket<MultipleChannels>;
+
+/// whether a peer plays or only watches
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Role {
+    #[default]
+    Player,
+    Spectator,
+}
+
+/// roles announced by the other peers in the room
+#[derive(Resource, Default, Debug)]
+pub struct PeerRoles(pub HashMap<PeerId, Role>);
+
+/// what a peer would like the match to be, only the host's wishes are played with
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct MatchSettings {
+    pub bots: BotSettings,
+    pub format: MatchFormat,
+    pub health: HealthRules,
+}
+
+/// the match settings each peer in the room asked for
+#[derive(Resource, Default, Debug)]
+pub struct PeerSettings(pub HashMap<PeerId, MatchSettings>);
+
+/// the input delay the running session was started with, which may have been picked from the
+/// ping rather than `NetworkConfig::input_delay`
+#[derive(Resource, Debug)]
+pub struct InputDelay(pub usize);
+
+/// the answer to our request for a ticket into a password protected room, once it arrives
+#[derive(Resource)]
+pub struct PendingTicket(Arc<Mutex<Option<Result<String, String>>>>);
+
+/// when we started connecting, in real seconds
+#[derive(Resource, Debug)]
+pub struct JoinedAt(pub f64);
+
+/// the random numbers that make up the match's seed, ours and what each peer announced
+#[derive(Resource, Debug)]
+pub struct SeedNonces {
+    pub ours: u64,
+    pub peers: HashMap<PeerId, u64>,
+}
+
+/// messages sent over the lobby channel
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum LobbyMessage {
+    /// sent to every peer once connected
+    Hello {
+        role: Role,
+        settings: MatchSettings,
+        nonce: u64,
+    },
+    /// answered with a `Pong` carrying the same time, to measure round trips
+    Ping {
+        sent: f64,
+    },
+    Pong {
+        sent: f64,
+    },
+    /// the host's answer to a spectator it can no longer take in
+    Started,
+}
+
+impl LobbyMessage {
+    pub fn to_packet(&self) -> Packet {
+        bincode::serialize(self)
+            .expect("Could not serialize lobby message.")
+            .into_boxed_slice()
+    }
+
+    pub fn from_packet(packet: &[u8]) -> Option<Self> {
+        bincode::deserialize(packet).ok()
+    }
+}
+
+pub const MIN_PLAYERS: usize = 2;
+pub const MAX_PLAYERS: usize = 8;
+pub const MAX_INPUT_DELAY: usize = 8;
+/// ggrs keeps up to this many frames of snapshots for its prediction window, and defaults to 8
+pub const MAX_PREDICTION: usize = 16;
+/// seconds a spectator waits for anyone to play before giving up on the room
+pub const SPECTATE_TIMEOUT: f64 = 30.;
+
+/// where and how to find other players
+#[derive(Resource, Debug, Clone)]
+pub struct NetworkConfig {
+    /// signaling server, i.e. `ws://localhost:3536`
+    pub host: String,
+    /// the public matchmaking room
+    pub room: String,
+    /// a private room code, set from the main menu
+    pub room_code: Option<String>,
+    /// lists a room we open in the room browser under this name
+    pub room_name: Option<String>,
+    /// needed to join protected rooms, and protects any room we open
+    pub password: Option<String>,
+    pub players: usize,
+    pub role: Role,
+    /// how long to wait for a peer whose connection dropped before they forfeit
+    pub disconnect_timeout: Duration,
+    /// frames between pressing a button and it taking effect, hides latency at the cost of responsiveness
+    pub input_delay: usize,
+    /// pick `input_delay` from the ping to the other players when the match starts
+    pub auto_input_delay: bool,
+    /// frames we may run ahead of the inputs we've received before waiting for them
+    pub max_prediction: usize,
+    /// bots filling extra player slots, online or in a local match
+    pub bots: BotSettings,
+    /// when the match is over, online or in a local match
+    pub format: MatchFormat,
+    /// how many hits players take, online or in a local match
+    pub health: HealthRules,
+}
+
+impl FromWorld for NetworkConfig {
+    fn from_world(world: &mut World) -> Self {
+        let options = world
+            .get_resource::<LaunchOptions>()
+            .cloned()
+            .unwrap_or_default();
+        Self {
+            host: options
+                .get("host")
+                .unwrap_or("ws://sushicat.rocks:9998")
+                .trim_end_matches('/')
+                .to_owned(),
+            room: sanitize_room(options.get("room").unwrap_or("p2pg")),
+            room_code: None,
+            room_name: None,
+            password: None,
+            role: Role::Player,
+            players: options
+                .parse("players")
+                .unwrap_or(MIN_PLAYERS)
+                .clamp(MIN_PLAYERS, MAX_PLAYERS),
+            // negative, infinite or nan timeouts fall back to the default
+            disconnect_timeout: options
+                .parse("disconnect-timeout")
+                .and_then(|secs: f32| Duration::try_from_secs_f32(secs).ok())
+                .unwrap_or(Duration::from_secs(10)),
+            // `auto` isn't a number, and picks one once we know the ping
+            input_delay: options
+                .get("input-delay")
+                .filter(|delay| *delay != "auto")
+                .and_then(|_| options.parse("input-delay"))
+                .unwrap_or(2)
+                .min(MAX_INPUT_DELAY),
+            auto_input_delay: options.get("input-delay") == Some("auto"),
+            max_prediction: options
+                .parse("max-prediction")
+                .unwrap_or(8)
+                .clamp(1, MAX_PREDICTION),
+            bots: BotSettings::from_options(&options),
+            format: MatchFormat::from_options(&options),
+            health: HealthRules::from_options(&options),
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// the signaling url of the room to join
+    pub fn room_url(&self) -> String {
+        let room = match &self.room_code {
+            Some(code) => format!("{}-{}", self.room, code),
+            None => self.room.clone(),
+        };
+        let mut url = format!("{}/{}?next={}", self.host, room, self.players);
+        if self.role == Role::Spectator {
+            // spectators don't take up one of the room's player slots
+            url.push_str("&spectate=true");
+        }
+        if let Some(name) = &self.room_name {
+            url.push_str(&format!(
+                "&name={}&map={}",
+                encode_query(name),
+                encode_query(MAP_PATH)
+            ));
+        }
+        url
+    }
+
+    /// where the signaling server lists its open rooms
+    pub fn rooms_url(&self) -> String {
+        format!("{}/rooms", self.http_host())
+    }
+
+    /// where the signaling server trades a password for a ticket to join with, so the password
+    /// never ends up in a url
+    pub fn tickets_url(&self) -> String {
+        format!("{}/tickets", self.http_host())
+    }
+
+    /// the same server, over http
+    fn http_host(&self) -> String {
+        if let Some(rest) = self.host.strip_prefix("wss://") {
+            format!("https://{rest}")
+        } else if let Some(rest) = self.host.strip_prefix("ws://") {
+            format!("http://{rest}")
+        } else {
+            self.host.clone()
+        }
+    }
+
+    /// the part of a room id after our public room, if it is one of ours
+    pub fn code_of<'a>(&self, room_id: &'a str) -> Option<&'a str> {
+        room_id
+            .strip_prefix(&self.room)
+            .and_then(|rest| rest.strip_prefix('-'))
+    }
+}
+
+/// percent-encode everything but unreserved characters, for use in a query string
+fn encode_query(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// strip anything that doesn't belong in a url path segment from a room name
+pub fn sanitize_room(room: &str) -> String {
+    room.chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .collect::<String>()
+        .to_lowercase()
+}
+
 pub struct NetworkingPlugin;
 impl Plugin for NetworkingPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(GgrsPlugin::<GgrsConfig>::default())
-            .add_systems(ReadInputs, input::input)
+        app.init_resource::<NetworkConfig>()
+            .init_resource::<ConnectionStatus>()
+            .init_resource::<SimulatedFrames>()
+            .add_plugins(GgrsPlugin::<GgrsConfig>::default())
+            .add_systems(
+                AdvanceWorld,
+                count_simulated_frames.in_set(AdvanceWorldSet::Last),
+            )
             .set_rollback_schedule_fps(60)
-            .rollback_component_with_clone::<Transform>()
+            .rollback_component_with_copy::<Position>()
             .rollback_component_with_copy::<CanShoot>()
             .rollback_component_with_copy::<Velocity>()
             .rollback_component_with_copy::<Lifetime>()
             .rollback_component_with_copy::<InputAngle>()
             .rollback_component_with_copy::<WallContactState>()
             .rollback_component_with_copy::<Health>()
+            .rollback_component_with_copy::<SinceHit>()
+            .rollback_component_with_copy::<crate::ammo::Quiver>()
             .rollback_component_with_copy::<Points>()
+            .rollback_component_with_copy::<RoundWins>()
             .rollback_component_with_copy::<LastDamagedBy>()
+            .rollback_component_with_copy::<BotBrain>()
+            .rollback_component_with_copy::<Bullet>()
+            .rollback_component_with_copy::<crate::weapon::Equipped>()
+            .rollback_component_with_copy::<crate::collision::Hitbox>()
             .rollback_resource_with_copy::<Rng>()
-            .rollback_resource_with_copy::<GameFrameCount>();
+            .rollback_resource_with_copy::<GameFrameCount>()
+            .rollback_resource_with_copy::<MatchState>();
+    }
+}
+
+/// number of players taking part in a session
+pub fn session_num_players(session: &Session<GgrsConfig>) -> usize {
+    match session {
+        Session::P2P(s) => s.num_players(),
+        Session::Spectator(s) => s.num_players(),
+        Session::SyncTest(s) => s.num_players(),
     }
 }
 
+/// round trip times to each peer measured over the lobby channel while connecting, in seconds
+#[derive(Resource, Default, Debug)]
+pub struct Pings(pub HashMap<PeerId, VecDeque<f32>>);
+
+impl Pings {
+    /// samples kept per peer
+    const SAMPLES: usize = 8;
+
+    fn record(&mut self, peer: PeerId, rtt: f32) {
+        let samples = self.0.entry(peer).or_default();
+        samples.push_back(rtt);
+        if samples.len() > Self::SAMPLES {
+            samples.pop_front();
+        }
+    }
+
+    pub fn average(&self, peer: PeerId) -> Option<f32> {
+        let samples = self.0.get(&peer).filter(|s| !s.is_empty())?;
+        Some(samples.iter().sum::<f32>() / samples.len() as f32)
+    }
+}
+
+/// enough input delay to cover the trip our inputs make to the other side
+pub fn auto_input_delay(rtt: f32) -> usize {
+    let one_way_frames = rtt / 2. * 60.;
+    (one_way_frames.round() as usize).min(MAX_INPUT_DELAY)
+}
+
+/// the players, not spectators, a remote peer controls
+pub fn player_handles(session: &Session<GgrsConfig>, peer: PeerId) -> Vec<usize> {
+    let Session::P2P(s) = session else {
+        return vec![];
+    };
+    s.handles_by_address(peer)
+        .into_iter()
+        .filter(|handle| *handle < s.num_players())
+        .collect()
+}
+
+/// frames simulated by ggrs, counting every resimulation after a rollback
+#[derive(Resource, Default, Debug)]
+pub struct SimulatedFrames(pub u64);
+
+fn count_simulated_frames(mut frames: ResMut<SimulatedFrames>) {
+    frames.0 += 1;
+}
+
+/// players whose connection has dropped during the match
+#[derive(Resource, Default, Debug)]
+pub struct ConnectionStatus {
+    /// players we haven't heard from lately, and the elapsed time at which they forfeit
+    pub interrupted: HashMap<usize, f32>,
+    /// players that left the match for good
+    pub disconnected: Vec<usize>,
+    /// everyone else left, so the match is over
+    pub forfeit: bool,
+}
+
 /// process ggrs events
 pub fn process_ggrs_events(
+    mut commands: Commands,
     session: Option<ResMut<Session<GgrsConfig>>>,
     mut next_state: ResMut<NextState<GameState>>,
+    mut status: ResMut<ConnectionStatus>,
+    history: Res<StateHistory>,
+    local_player: Option<Res<LocalPlayer>>,
+    time: Res<Time>,
 ) {
-    use ggrs::GgrsEvent;
-
     let Some(mut session) = session else {
         return;
     };
-    let Session::P2P(session) = session.as_mut() else {
-        return;
+    let events: Vec<GgrsEvent<GgrsConfig>> = match session.as_mut() {
+        Session::P2P(s) => s.events().collect(),
+        Session::Spectator(s) => s.events().collect(),
+        Session::SyncTest(_) => return,
     };
-    for event in session.events() {
+    for event in events {
         info!("GGRS Event: {event:?}");
         match event {
-            GgrsEvent::Disconnected { .. } => {
-                warn!("Disconneted. Returning to lobby...");
+            GgrsEvent::NetworkInterrupted {
+                addr,
+                disconnect_timeout,
+            } => {
+                let deadline = time.elapsed_seconds() + disconnect_timeout as f32 / 1000.;
+                for handle in player_handles(&session, addr) {
+                    status.interrupted.insert(handle, deadline);
+                }
+            }
+            GgrsEvent::NetworkResumed { addr } => {
+                for handle in player_handles(&session, addr) {
+                    status.interrupted.remove(&handle);
+                }
+            }
+            GgrsEvent::Disconnected { .. } if matches!(*session, Session::Spectator(_)) => {
+                warn!("Lost the host. Returning to lobby...");
                 next_state.set(GameState::Lobby);
             }
+            GgrsEvent::Disconnected { addr } => {
+                for handle in player_handles(&session, addr) {
+                    warn!("Player {} left the match", handle + 1);
+                    status.interrupted.remove(&handle);
+                    status.disconnected.push(handle);
+                }
+                // ggrs keeps feeding the others empty inputs, so the match only ends once we're alone
+                let remaining = session_num_players(&session) - status.disconnected.len();
+                if remaining <= 1 {
+                    info!("Everyone else left, winning by forfeit");
+                    status.forfeit = true;
+                    commands.remove_resource::<Session<GgrsConfig>>();
+                    return;
+                }
+            }
+            GgrsEvent::DesyncDetected {
+                frame,
+                local_checksum,
+                remote_checksum,
+                addr,
+            } => {
+                error!(
+                    "Desync with {addr} on frame {frame}: local checksum {local_checksum:x}, remote {remote_checksum:x}"
+                );
+                let dump = desync::write_dump(&history, frame, local_player.as_ref().map(|p| p.id));
+                commands.insert_resource(Desync {
+                    frame,
+                    peer: addr,
+                    dump,
+                });
+                // there's no recovering from here, stop simulating but leave everything on screen
+                commands.remove_resource::<Session<GgrsConfig>>();
+                return;
+            }
             _ => (),
         }
     }
 }
 
-/// initialize the matchbox socket
-pub fn setup_socket(mut commands: Commands) {
-    let room_url = "ws://sushicat.rocks:9998/p2pg?next=2";
+/// initialize the matchbox socket, once we have a ticket if the room needs a password
+pub fn setup_socket(mut commands: Commands, config: Res<NetworkConfig>, time: Res<Time<Real>>) {
+    match &config.password {
+        Some(password) => {
+            let slot = Arc::new(Mutex::new(None));
+            let pending = slot.clone();
+            spawn_request(
+                config.tickets_url(),
+                Some(password.clone()),
+                move |ticket| {
+                    *pending.lock().unwrap() = Some(ticket);
+                },
+            );
+            commands.insert_resource(PendingTicket(slot));
+        }
+        None => open_socket(&mut commands, config.room_url()),
+    }
+    commands.insert_resource(PeerRoles::default());
+    commands.insert_resource(PeerSettings::default());
+    commands.insert_resource(Pings::default());
+    commands.insert_resource(JoinedAt(time.elapsed_seconds_f64()));
+    // when we happened to click is as unpredictable as anything else we have on every platform
+    commands.insert_resource(SeedNonces {
+        ours: time.elapsed().as_nanos() as u64,
+        peers: default(),
+    });
+}
+
+fn open_socket(commands: &mut Commands, room_url: String) {
     info!("connecting to room {}", room_url);
-    commands.insert_resource(MatchboxSocket::new_ggrs(room_url));
+    let socket = WebRtcSocketBuilder::new(room_url)
+        .add_ggrs_channel()
+        .add_reliable_channel()
+        .add_reliable_channel();
+    commands.insert_resource(MatchboxSocket::from(socket));
 }
 
-/// wait for 2 players to connect to the server, before transitioning to in-game
+/// join the password protected room with the ticket the signaling server handed us
+pub fn redeem_ticket(
+    mut commands: Commands,
+    pending: Option<Res<PendingTicket>>,
+    config: Res<NetworkConfig>,
+    browser: Option<ResMut<RoomBrowser>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let Some(ticket) = pending.and_then(|pending| pending.0.lock().unwrap().take()) else {
+        return;
+    };
+    commands.remove_resource::<PendingTicket>();
+    match ticket {
+        Ok(ticket) => {
+            let url = format!("{}&ticket={}", config.room_url(), encode_query(&ticket));
+            open_socket(&mut commands, url);
+        }
+        Err(e) => {
+            error!(
+                "could not get a ticket into room {}: {e}",
+                config.room_url()
+            );
+            if let Some(mut browser) = browser {
+                browser.error = Some(format!("Could not join the room: {e}"));
+            }
+            next_state.set(GameState::Lobby);
+        }
+    }
+}
+
+/// greet newly connected peers and record the roles other peers announce
+pub fn exchange_roles(
+    mut socket: ResMut<GameSocket>,
+    mut roles: ResMut<PeerRoles>,
+    mut settings: ResMut<PeerSettings>,
+    mut pings: ResMut<Pings>,
+    mut nonces: ResMut<SeedNonces>,
+    config: Res<NetworkConfig>,
+    mut browser: Option<ResMut<RoomBrowser>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    time: Res<Time<Real>>,
+    joined_at: Res<JoinedAt>,
+    mut last_ping: Local<f64>,
+) {
+    if socket.get_channel(LOBBY_CHANNEL).is_err() {
+        return;
+    }
+    let Ok(peers) = socket.try_update_peers() else {
+        // the signaling server couldn't be reached, or turned us away over a wrong password
+        error!("could not join room {}", config.room_url());
+        if let Some(browser) = browser.as_mut() {
+            browser.error = Some("Could not join the room".to_owned());
+        }
+        next_state.set(GameState::Lobby);
+        return;
+    };
+    for (peer, state) in peers {
+        match state {
+            PeerState::Connected => {
+                let hello = LobbyMessage::Hello {
+                    role: config.role,
+                    settings: MatchSettings {
+                        bots: config.bots,
+                        format: config.format,
+                        health: config.health,
+                    },
+                    nonce: nonces.ours,
+                };
+                socket.channel(LOBBY_CHANNEL).send(hello.to_packet(), peer);
+            }
+            PeerState::Disconnected => {
+                roles.0.remove(&peer);
+                settings.0.remove(&peer);
+                pings.0.remove(&peer);
+                nonces.peers.remove(&peer);
+            }
+        }
+    }
+
+    // keep measuring the ping for as long as we wait, a handful of samples smooths out spikes
+    let now = time.elapsed_seconds_f64();
+    if now - *last_ping > 0.25 {
+        *last_ping = now;
+        let peers: Vec<PeerId> = socket.connected_peers().collect();
+        for peer in peers {
+            let ping = LobbyMessage::Ping { sent: now };
+            socket.channel(LOBBY_CHANNEL).send(ping.to_packet(), peer);
+        }
+    }
+
+    for (peer, packet) in socket.channel(LOBBY_CHANNEL).receive() {
+        match LobbyMessage::from_packet(&packet) {
+            Some(LobbyMessage::Hello {
+                role,
+                settings: wanted,
+                nonce,
+            }) => {
+                info!("peer {peer} joined as {role:?}");
+                roles.0.insert(peer, role);
+                settings.0.insert(peer, wanted);
+                nonces.peers.insert(peer, nonce);
+            }
+            Some(LobbyMessage::Ping { sent }) => {
+                let pong = LobbyMessage::Pong { sent };
+                socket.channel(LOBBY_CHANNEL).send(pong.to_packet(), peer);
+            }
+            Some(LobbyMessage::Pong { sent }) => pings.record(peer, (now - sent) as f32),
+            Some(LobbyMessage::Started) => {
+                too_late(browser.as_deref_mut(), &mut next_state);
+                return;
+            }
+            None => warn!("malformed lobby message from {peer}"),
+        }
+    }
+
+    // a sealed room sends spectators into a fresh one, where nobody may ever turn up to play
+    let anyone_playing = roles.0.values().any(|role| *role == Role::Player);
+    if config.role == Role::Spectator && !anyone_playing && now - joined_at.0 > SPECTATE_TIMEOUT {
+        warn!("nobody came to play, giving up on spectating");
+        if let Some(browser) = browser.as_mut() {
+            browser.error = Some("Nobody is playing in this room".to_owned());
+        }
+        next_state.set(GameState::Lobby);
+    }
+}
+
+/// keep listening on the lobby channel during the match. the host turns away spectators it heard
+/// from too late to take into its session, and those spectators head back to the lobby
+pub fn answer_latecomers(
+    socket: Option<ResMut<GameSocket>>,
+    session: Option<Res<Session<GgrsConfig>>>,
+    local_player: Option<Res<LocalPlayer>>,
+    mut browser: Option<ResMut<RoomBrowser>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let Some(mut socket) = socket else {
+        return;
+    };
+    if socket.get_channel(LOBBY_CHANNEL).is_err() {
+        return;
+    }
+    // the first player hosts the spectators
+    let hosting = matches!(session.as_deref(), Some(Session::P2P(_)))
+        && local_player.is_some_and(|player| player.id == 0);
+    for (peer, packet) in socket.channel(LOBBY_CHANNEL).receive() {
+        match LobbyMessage::from_packet(&packet) {
+            Some(LobbyMessage::Hello {
+                role: Role::Spectator,
+                ..
+            }) if hosting => {
+                info!("spectator {peer} turned up after the match started");
+                socket
+                    .channel(LOBBY_CHANNEL)
+                    .send(LobbyMessage::Started.to_packet(), peer);
+            }
+            Some(LobbyMessage::Started) => {
+                too_late(browser.as_deref_mut(), &mut next_state);
+                return;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn too_late(browser: Option<&mut RoomBrowser>, next_state: &mut NextState<GameState>) {
+    warn!("the match already started without us");
+    if let Some(browser) = browser {
+        browser.error = Some("The match already started".to_owned());
+    }
+    next_state.set(GameState::Lobby);
+}
+
+/// wait for all players to connect to the server, before transitioning to in-game
 pub fn wait_for_players(
     mut commands: Commands,
-    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    mut socket: ResMut<GameSocket>,
     mut next_state: ResMut<NextState<GameState>>,
+    config: Res<NetworkConfig>,
+    roles: Res<PeerRoles>,
+    peer_settings: Res<PeerSettings>,
+    pings: Res<Pings>,
+    nonces: Res<SeedNonces>,
 ) {
     // this will return when the channel has been taken ownership of
-    if socket.get_channel(0).is_err() {
+    if socket.get_channel(GGRS_CHANNEL).is_err() {
+        return;
+    }
+    let Some(our_id) = socket.id() else {
+        return;
+    };
+    // every connected peer has to have told us what they are
+    let connected: Vec<PeerId> = socket.connected_peers().collect();
+    if connected.iter().any(|peer| !roles.0.contains_key(peer)) {
         return;
     }
-    socket.update_peers();
 
-    let num_players = 2;
-    let players = socket.players();
+    let num_players = config.players;
+    // player order needs to be consistent across all peers
+    let mut players: Vec<PeerId> = connected
+        .iter()
+        .copied()
+        .filter(|peer| roles.0[peer] == Role::Player)
+        .collect();
+    if config.role == Role::Player {
+        players.push(our_id);
+    }
+    players.sort();
     if players.len() < num_players {
         return;
     }
+    players.truncate(num_players);
+
+    // picked for this match only, the setting stays `auto`
+    let mut input_delay = config.input_delay;
+    if config.auto_input_delay && config.role == Role::Player {
+        // the furthest player decides, everyone else is at least as close
+        let mut worst = 0f32;
+        for peer in players.iter().filter(|peer| **peer != our_id) {
+            let Some(rtt) = pings.average(*peer) else {
+                // wait for at least one round trip
+                return;
+            };
+            worst = worst.max(rtt);
+        }
+        input_delay = auto_input_delay(worst);
+        info!(
+            "picked input delay {} for a {}ms ping",
+            input_delay,
+            (worst * 1000.).round()
+        );
+    }
 
     info!("All players connected.");
 
-    let mut session_builder = ggrs::SessionBuilder::<GgrsConfig>::new()
+    // spectators heard from every player too, so they end up with the same seed
+    let seed = Seed::combine(players.iter().map(|peer| {
+        if *peer == our_id {
+            nonces.ours
+        } else {
+            nonces.peers[peer]
+        }
+    }));
+    info!("agreed on seed {}", seed.0);
+
+    // the first player hosts all spectators and its bots, and picks the rules
+    let host = players[0];
+    let MatchSettings {
+        mut bots,
+        format,
+        health,
+    } = if host == our_id {
+        MatchSettings {
+            bots: config.bots,
+            format: config.format,
+            health: config.health,
+        }
+    } else {
+        peer_settings.0[&host]
+    };
+    let humans = num_players;
+    bots.count = bots.count.min(MAX_PLAYERS - humans);
+    let num_players = humans + bots.count;
+
+    let session_builder = ggrs::SessionBuilder::<GgrsConfig>::new()
         .with_num_players(num_players)
-        .with_input_delay(2)
+        .with_input_delay(input_delay)
+        .with_max_prediction_window(config.max_prediction)
+        .expect("Could not set prediction window.")
+        .with_disconnect_timeout(config.disconnect_timeout)
+        .with_disconnect_notify_delay(Duration::from_millis(500))
         .with_desync_detection_mode(ggrs::DesyncDetection::On { interval: 10 });
 
-    for (id, player) in players.into_iter().enumerate() {
-        if player == ggrs::PlayerType::Local {
-            commands.insert_resource(LocalPlayer { id });
-        }
-        session_builder = session_builder
-            .add_player(player, id)
-            .expect("Could not add player to session");
-    }
+    let channel = socket.take_channel(GGRS_CHANNEL).unwrap();
 
-    // give ownership of the channel
-    let channel = socket.take_channel(0).unwrap();
-    let ggrs_session = session_builder
-        .start_p2p_session(channel)
-        .expect("Could not init p2p session.");
+    let session = match config.role {
+        Role::Spectator => {
+            info!("Spectating, host is {host}");
+            Session::Spectator(session_builder.start_spectator_session(host, channel))
+        }
+        Role::Player => {
+            let mut session_builder = session_builder;
+            for (id, peer) in players.iter().enumerate() {
+                let player = if *peer == our_id {
+                    commands.insert_resource(LocalPlayer { id });
+                    ggrs::PlayerType::Local
+                } else {
+                    ggrs::PlayerType::Remote(*peer)
+                };
+                session_builder = session_builder
+                    .add_player(player, id)
+                    .expect("Could not add player to session");
+            }
+            for id in humans..num_players {
+                // the host sends along whatever for its bots, the simulation decides their inputs
+                let player = if host == our_id {
+                    ggrs::PlayerType::Local
+                } else {
+                    ggrs::PlayerType::Remote(host)
+                };
+                session_builder = session_builder
+                    .add_player(player, id)
+                    .expect("Could not add bot to session");
+            }
+            if host == our_id {
+                let mut spectators: Vec<PeerId> = connected
+                    .iter()
+                    .copied()
+                    .filter(|peer| roles.0[peer] == Role::Spectator)
+                    .collect();
+                spectators.sort();
+                for (i, spectator) in spectators.into_iter().enumerate() {
+                    session_builder = session_builder
+                        .add_player(ggrs::PlayerType::Spectator(spectator), num_players + i)
+                        .expect("Could not add spectator to session");
+                }
+            }
+            let ggrs_session = session_builder
+                .start_p2p_session(channel)
+                .expect("Could not init p2p session.");
+            Session::P2P(ggrs_session)
+        }
+    };
 
-    commands.insert_resource(Session::P2P(ggrs_session));
+    commands.insert_resource(session);
+    commands.insert_resource(InputDelay(input_delay));
+    commands.insert_resource(bots);
+    commands.insert_resource(format);
+    commands.insert_resource(health);
+    commands.insert_resource(seed);
     next_state.set(GameState::Game);
 }
+
+#[test]
+fn listed_rooms_are_encoded_into_the_url() {
+    let mut world = World::new();
+    world.insert_resource(LaunchOptions::default());
+    let mut config = NetworkConfig::from_world(&mut world);
+    config.host = "ws://localhost:3536".to_owned();
+    config.room_code = Some("abc".to_owned());
+    config.room_name = Some("snow & ice".to_owned());
+    // passwords are traded for a ticket instead
+    config.password = Some("p/w".to_owned());
+    assert_eq!(
+        config.room_url(),
+        "ws://localhost:3536/p2pg-abc?next=2&name=snow%20%26%20ice&map=snowy.tmx"
+    );
+    assert_eq!(config.rooms_url(), "http://localhost:3536/rooms");
+    config.host = "wss://example.com".to_owned();
+    assert_eq!(config.tickets_url(), "https://example.com/tickets");
+    config.host = "http://news.example.com".to_owned();
+    assert_eq!(config.rooms_url(), "http://news.example.com/rooms");
+    assert_eq!(config.code_of("p2pg-abc"), Some("abc"));
+    assert_eq!(config.code_of("other-abc"), None);
+}
+
+#[test]
+fn auto_input_delay_covers_half_the_round_trip() {
+    assert_eq!(auto_input_delay(0.002), 0);
+    assert_eq!(auto_input_delay(0.1), 3);
+    assert_eq!(auto_input_delay(2.), MAX_INPUT_DELAY);
+}
+
+#[test]
+fn unusable_disconnect_timeouts_fall_back_to_the_default() {
+    for timeout in ["-1", "NaN", "inf"] {
+        let mut world = World::new();
+        world.insert_resource(LaunchOptions::parse_args(
+            ["--disconnect-timeout", timeout].map(String::from),
+        ));
+        let config = NetworkConfig::from_world(&mut world);
+        assert_eq!(config.disconnect_timeout, Duration::from_secs(10));
+    }
+}
+
+#[test]
+fn auto_input_delay_is_not_a_number() {
+    let mut world = World::new();
+    world.insert_resource(LaunchOptions::parse_args(
+        ["--input-delay", "auto"].map(String::from),
+    ));
+    let config = NetworkConfig::from_world(&mut world);
+    assert!(config.auto_input_delay);
+    assert_eq!(config.input_delay, 2);
+}
@@ -1,7 +1,7 @@
 use bevy::prelude::*;
 
 // https://en.wikipedia.org/wiki/Linear_congruential_generator
-#[derive(Resource, Clone, Copy, Reflect, Debug)]
+#[derive(Resource, Clone, Copy, Reflect, Debug, Hash)]
 pub struct Rng {
     x: u64, // seed
     m: u64,
@@ -20,6 +20,48 @@ impl Default for Rng {
     }
 }
 
+/// what the rng of the next match starts from
+pub const DEFAULT_SEED: u64 = 8008135;
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct Seed(pub u64);
+
+impl Default for Seed {
+    fn default() -> Self {
+        Self(DEFAULT_SEED)
+    }
+}
+
+impl Seed {
+    /// mixes the nonces every player contributed, in player order, into a seed none of them picked
+    pub fn combine(nonces: impl IntoIterator<Item = u64>) -> Self {
+        let mut seed = 0u64;
+        for nonce in nonces {
+            seed = splitmix64(seed ^ nonce);
+        }
+        Self::from_raw(seed)
+    }
+
+    /// a seed for a match with no one to agree with
+    pub fn local(nonce: u64) -> Self {
+        Self::from_raw(splitmix64(nonce))
+    }
+
+    /// the generator only takes seeds in [1, m), zero would get it stuck
+    fn from_raw(x: u64) -> Self {
+        let m = Rng::default().m;
+        Self(x % (m - 1) + 1)
+    }
+}
+
+// https://prng.di.unimi.it/splitmix64.c
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
 #[allow(dead_code)]
 impl Rng {
     pub fn new(seed: u64) -> Self {
@@ -70,3 +112,12 @@ fn evenly_distributed() {
     avg /= count as f64;
     assert!(0.5 - epsilon < avg && avg < 0.5 + epsilon);
 }
+
+#[test]
+fn every_nonce_changes_the_combined_seed() {
+    let seed = Seed::combine([1, 2, 3]).0;
+    assert_ne!(seed, Seed::combine([1, 2, 4]).0);
+    assert_ne!(seed, Seed::combine([2, 1, 3]).0);
+    assert_eq!(seed, Seed::combine([1, 2, 3]).0);
+    assert!((1..Rng::default().m).contains(&Seed::combine([0]).0));
+}
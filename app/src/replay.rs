@@ -0,0 +1,352 @@
+use std::{fmt, path::PathBuf};
+
+use bevy::prelude::*;
+use bevy_ggrs::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::LaunchOptions,
+    health::HealthRules,
+    input::PlayerInput,
+    p2p::{session_num_players, GgrsConfig},
+    rand::Seed,
+    rounds::MatchFormat,
+    GameState, MAP_PATH,
+};
+
+/// bumped whenever the file layout or the simulation changes in a way old replays can't follow
+pub const REPLAY_VERSION: u32 = 9;
+const MAGIC: &[u8; 4] = b"P2PR";
+/// where finished matches are saved, relative to the working directory
+pub const REPLAY_DIR: &str = "replays";
+
+/// a whole match: the world it started from, then every player's input for every frame
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct Replay {
+    pub map: String,
+    pub seed: u64,
+    pub num_players: usize,
+    pub format: MatchFormat,
+    pub health: HealthRules,
+    /// one entry per frame, starting at frame 1
+    pub frames: Vec<Vec<PlayerInput>>,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    NotAReplay,
+    UnsupportedVersion(u32),
+    Malformed(bincode::Error),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "could not read replay: {e}"),
+            Self::NotAReplay => write!(f, "not a replay file"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "replay is version {v}, this build plays version {REPLAY_VERSION}"
+            ),
+            Self::Malformed(e) => write!(f, "replay is corrupt: {e}"),
+        }
+    }
+}
+
+impl Replay {
+    /// `P2PR`, the little endian format version, then the bincode encoded replay
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(REPLAY_VERSION.to_le_bytes());
+        bytes.extend(bincode::serialize(self).expect("Could not serialize replay."));
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let bytes = bytes.strip_prefix(MAGIC).ok_or(ReplayError::NotAReplay)?;
+        let (version, body) = bytes
+            .split_first_chunk::<4>()
+            .ok_or(ReplayError::NotAReplay)?;
+        let version = u32::from_le_bytes(*version);
+        if version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+        bincode::deserialize(body).map_err(ReplayError::Malformed)
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Self, ReplayError> {
+        Self::from_bytes(&std::fs::read(path).map_err(ReplayError::Io)?)
+    }
+}
+
+/// saved replays, newest first
+#[cfg(not(target_arch = "wasm32"))]
+pub fn list_replays() -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(REPLAY_DIR) else {
+        return vec![];
+    };
+    let mut replays: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "replay"))
+        .collect();
+    replays.sort();
+    replays.reverse();
+    replays
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn list_replays() -> Vec<PathBuf> {
+    vec![]
+}
+
+/// the inputs of the match being played, as ggrs simulates them
+#[derive(Resource, Debug)]
+pub struct ReplayRecorder {
+    replay: Replay,
+    /// frames up to here won't be resimulated, so their inputs are final
+    confirmed: i32,
+}
+
+/// a replay to start watching the next time we're in the lobby
+#[derive(Resource, Debug)]
+pub struct WatchReplay(pub PathBuf);
+
+/// the replay currently being watched
+#[derive(Resource, Debug)]
+pub struct ReplayPlayback {
+    pub replay: Replay,
+    pub finished: bool,
+}
+
+pub struct ReplayPlugin;
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(path) = app
+            .world
+            .get_resource::<LaunchOptions>()
+            .and_then(|options| options.get("replay"))
+        {
+            app.insert_resource(WatchReplay(path.into()));
+        }
+        app.add_systems(
+            OnEnter(GameState::Game),
+            (
+                start_recording,
+                start_playback_session
+                    .before(crate::spawn_players)
+                    .run_if(resource_exists::<ReplayPlayback>()),
+            ),
+        )
+        .add_systems(
+            GgrsSchedule,
+            // bots are recorded like everyone else, so playing back doesn't need to know about them
+            record_inputs
+                .after(crate::bot::bot_input)
+                .before(crate::rounds::freeze_inputs)
+                .run_if(resource_exists::<ReplayRecorder>()),
+        )
+        .add_systems(OnExit(GameState::Game), save_replay)
+        .add_systems(
+            Update,
+            (
+                start_playback
+                    .run_if(in_state(GameState::Lobby).and_then(resource_exists::<WatchReplay>())),
+                finish_playback.run_if(
+                    in_state(GameState::Game).and_then(resource_exists::<ReplayPlayback>()),
+                ),
+            ),
+        )
+        .add_systems(
+            ReadInputs,
+            replay_input.run_if(resource_exists::<ReplayPlayback>()),
+        );
+    }
+}
+
+/// record online matches, whether playing or spectating
+fn start_recording(
+    mut commands: Commands,
+    session: Option<Res<Session<GgrsConfig>>>,
+    seed: Res<Seed>,
+    format: Res<MatchFormat>,
+    health: Res<HealthRules>,
+) {
+    let Some(session) = session else {
+        return;
+    };
+    if let Session::SyncTest(_) = session.as_ref() {
+        return;
+    }
+    commands.insert_resource(ReplayRecorder {
+        replay: Replay {
+            map: MAP_PATH.to_owned(),
+            seed: seed.0,
+            num_players: session_num_players(&session),
+            format: *format,
+            health: *health,
+            frames: vec![],
+        },
+        confirmed: 0,
+    });
+}
+
+fn record_inputs(
+    mut recorder: ResMut<ReplayRecorder>,
+    inputs: Res<PlayerInputs<GgrsConfig>>,
+    frame: Res<RollbackFrameCount>,
+    confirmed: Res<ConfirmedFrameCount>,
+) {
+    let frame = i32::from(*frame);
+    // a resimulated frame replaces the predicted inputs it was first run with
+    recorder.replay.frames.truncate(frame as usize - 1);
+    recorder
+        .replay
+        .frames
+        .push(inputs.iter().map(|(input, _)| *input).collect());
+    recorder.confirmed = recorder.confirmed.max(i32::from(*confirmed));
+}
+
+fn save_replay(mut commands: Commands, recorder: Option<ResMut<ReplayRecorder>>) {
+    let Some(mut recorder) = recorder else {
+        return;
+    };
+    commands.remove_resource::<ReplayRecorder>();
+    // anything past the last confirmed frame may have been mispredicted
+    let confirmed = recorder.confirmed.max(0) as usize;
+    recorder.replay.frames.truncate(confirmed);
+    if recorder.replay.frames.is_empty() {
+        return;
+    }
+    write_replay(&recorder.replay);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_replay(replay: &Replay) {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let path = PathBuf::from(REPLAY_DIR).join(format!("{secs}.replay"));
+    let written =
+        std::fs::create_dir_all(REPLAY_DIR).and_then(|_| std::fs::write(&path, replay.to_bytes()));
+    match written {
+        Ok(()) => info!(
+            "saved {} frame replay to {}",
+            replay.frames.len(),
+            path.display()
+        ),
+        Err(e) => error!("could not save replay to {}: {e}", path.display()),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_replay(_replay: &Replay) {
+    info!("replays can't be saved in the browser");
+}
+
+/// play the replay back through a session that never rolls back, see `start_playback_session`
+fn start_playback(
+    mut commands: Commands,
+    watch: Res<WatchReplay>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    commands.remove_resource::<WatchReplay>();
+    let replay = match Replay::load(&watch.0) {
+        Ok(replay) => replay,
+        Err(e) => {
+            error!("{}: {e}", watch.0.display());
+            return;
+        }
+    };
+    if replay.map != MAP_PATH {
+        warn!(
+            "replay was recorded on {}, playing it on {MAP_PATH}",
+            replay.map
+        );
+    }
+    info!(
+        "watching {}, {} frames",
+        watch.0.display(),
+        replay.frames.len()
+    );
+
+    commands.insert_resource(Seed(replay.seed));
+    commands.insert_resource(replay.format);
+    commands.insert_resource(replay.health);
+    commands.insert_resource(ReplayPlayback {
+        replay,
+        finished: false,
+    });
+    next_state.set(GameState::Game);
+}
+
+/// the session only starts once we're in game, so no frames tick by before there's a game to simulate
+/// (exclusive, so players are spawned with the session already in place)
+fn start_playback_session(world: &mut World) {
+    let num_players = world.resource::<ReplayPlayback>().replay.num_players;
+    let session = ggrs::SessionBuilder::<GgrsConfig>::new()
+        .with_num_players(num_players)
+        .with_check_distance(0)
+        .start_synctest_session()
+        .expect("Could not start replay session.");
+    world.insert_resource(Session::SyncTest(session));
+}
+
+fn replay_input(
+    mut commands: Commands,
+    playback: Res<ReplayPlayback>,
+    local_players: Res<LocalPlayers>,
+    frame: Res<RollbackFrameCount>,
+) {
+    // the inputs being read are for the frame after this one
+    let Some(inputs) = playback.replay.frames.get(i32::from(*frame) as usize) else {
+        // without inputs ggrs won't advance, so playback stops exactly on the last recorded frame
+        commands.insert_resource(LocalInputs::<GgrsConfig>(default()));
+        return;
+    };
+    let local_inputs = local_players
+        .0
+        .iter()
+        .map(|&handle| (handle, inputs.get(handle).copied().unwrap_or_default()))
+        .collect();
+    commands.insert_resource(LocalInputs::<GgrsConfig>(local_inputs));
+}
+
+/// freeze on the last frame once the inputs run out
+fn finish_playback(
+    mut commands: Commands,
+    mut playback: ResMut<ReplayPlayback>,
+    frame: Res<RollbackFrameCount>,
+) {
+    if !playback.finished && i32::from(*frame) as usize >= playback.replay.frames.len() {
+        info!("replay finished");
+        playback.finished = true;
+        commands.remove_resource::<Session<GgrsConfig>>();
+    }
+}
+
+#[test]
+fn replays_round_trip() {
+    let replay = Replay {
+        map: MAP_PATH.to_owned(),
+        seed: 42,
+        num_players: 2,
+        format: MatchFormat::ScoreLimit(1000),
+        health: HealthRules::default(),
+        frames: vec![vec![PlayerInput::default(); 2]; 3],
+    };
+    let mut bytes = replay.to_bytes();
+    assert_eq!(Replay::from_bytes(&bytes).unwrap(), replay);
+
+    bytes[4] += 1;
+    assert!(matches!(
+        Replay::from_bytes(&bytes),
+        Err(ReplayError::UnsupportedVersion(_))
+    ));
+    assert!(matches!(
+        Replay::from_bytes(b"nope"),
+        Err(ReplayError::NotAReplay)
+    ));
+}
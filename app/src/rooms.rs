@@ -0,0 +1,164 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{p2p::NetworkConfig, GameState};
+
+/// how often the open room list is fetched again while in the lobby
+const REFRESH_SECS: f32 = 5.;
+
+/// a room listed by the signaling server
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct RoomInfo {
+    /// the room's path on the signaling server
+    pub id: String,
+    pub name: String,
+    pub map: Option<String>,
+    pub players: usize,
+    pub capacity: Option<usize>,
+    pub password: bool,
+}
+
+impl RoomInfo {
+    pub fn is_full(&self) -> bool {
+        self.capacity.is_some_and(|cap| self.players >= cap)
+    }
+}
+
+type FetchResult = Result<Vec<RoomInfo>, String>;
+
+/// the open rooms on the signaling server, as of the last fetch
+#[derive(Resource, Default)]
+pub struct RoomBrowser {
+    pub rooms: Vec<RoomInfo>,
+    /// why the last fetch, or the last attempt at joining a room, failed
+    pub error: Option<String>,
+    /// set to fetch the list again right away
+    pub refresh: bool,
+    /// filled in by the request in flight once it completes
+    pending: Option<Arc<Mutex<Option<FetchResult>>>>,
+    since_fetch: f32,
+}
+
+impl RoomBrowser {
+    pub fn fetching(&self) -> bool {
+        self.pending.is_some()
+    }
+}
+
+pub struct RoomBrowserPlugin;
+impl Plugin for RoomBrowserPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RoomBrowser>()
+            .add_systems(
+                OnEnter(GameState::Lobby),
+                |mut browser: ResMut<RoomBrowser>| {
+                    browser.refresh = true;
+                },
+            )
+            .add_systems(
+                Update,
+                (fetch_rooms, receive_rooms).run_if(in_state(GameState::Lobby)),
+            );
+    }
+}
+
+fn fetch_rooms(mut browser: ResMut<RoomBrowser>, config: Res<NetworkConfig>, time: Res<Time>) {
+    browser.since_fetch += time.delta_seconds();
+    if browser.fetching() || !(browser.refresh || browser.since_fetch > REFRESH_SECS) {
+        return;
+    }
+    browser.refresh = false;
+    browser.since_fetch = 0.;
+
+    let slot = Arc::new(Mutex::new(None));
+    browser.pending = Some(slot.clone());
+    let url = config.rooms_url();
+    spawn_request(url, None, move |result| {
+        let rooms = result.and_then(|body| serde_json::from_str(&body).map_err(|e| e.to_string()));
+        *slot.lock().unwrap() = Some(rooms);
+    });
+}
+
+fn receive_rooms(mut browser: ResMut<RoomBrowser>) {
+    let Some(result) = browser
+        .pending
+        .as_ref()
+        .and_then(|slot| slot.lock().unwrap().take())
+    else {
+        return;
+    };
+    browser.pending = None;
+    match result {
+        Ok(rooms) => {
+            browser.rooms = rooms;
+            browser.error = None;
+        }
+        Err(e) => {
+            warn!("could not list rooms: {e}");
+            browser.rooms.clear();
+            browser.error = Some(e);
+        }
+    }
+}
+
+/// an http request to the signaling server, a get or, with a `body`, a post. `done` is handed the
+/// response body once it arrives
+#[cfg(not(target_arch = "wasm32"))]
+pub fn spawn_request(
+    url: String,
+    body: Option<String>,
+    done: impl FnOnce(Result<String, String>) + Send + 'static,
+) {
+    std::thread::spawn(move || done(request(&url, body.as_deref())));
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn request(url: &str, body: Option<&str>) -> Result<String, String> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(std::time::Duration::from_secs(5))
+        .build();
+    let response = match body {
+        Some(body) => agent.post(url).send_string(body),
+        None => agent.get(url).call(),
+    };
+    match response {
+        Ok(response) => response.into_string().map_err(|e| e.to_string()),
+        Err(ureq::Error::Status(code, _)) => Err(format!("server answered {code}")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn spawn_request(
+    url: String,
+    body: Option<String>,
+    done: impl FnOnce(Result<String, String>) + 'static,
+) {
+    wasm_bindgen_futures::spawn_local(async move { done(request(&url, body).await) });
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn request(url: &str, body: Option<String>) -> Result<String, String> {
+    use wasm_bindgen::{JsCast, JsValue};
+    use wasm_bindgen_futures::JsFuture;
+
+    let window = web_sys::window().ok_or("no window")?;
+    let mut init = web_sys::RequestInit::new();
+    if let Some(body) = body {
+        // a plain text body keeps it a simple cross-origin request, without a preflight
+        init.method("POST").body(Some(&JsValue::from_str(&body)));
+    }
+    let response = JsFuture::from(window.fetch_with_str_and_init(url, &init))
+        .await
+        .map_err(|e| format!("{e:?}"))?;
+    let response: web_sys::Response = response.unchecked_into();
+    if !response.ok() {
+        return Err(format!("server answered {}", response.status()));
+    }
+    let text = JsFuture::from(response.text().map_err(|e| format!("{e:?}"))?)
+        .await
+        .map_err(|e| format!("{e:?}"))?;
+    Ok(text.as_string().unwrap_or_default())
+}
@@ -0,0 +1,296 @@
+use bevy::prelude::*;
+use bevy_ggrs::PlayerInputs;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    component::{Bullet, Health, LastDamagedBy, Player, Points, RoundWins},
+    config::LaunchOptions,
+    input::{FIRE, LEAVE, MOVE, REMATCH},
+    p2p::GgrsConfig,
+    GameFrameCount, GameState,
+};
+
+/// frames counted down before a round starts
+pub const COUNTDOWN_FRAMES: u32 = 180;
+/// frames the game stays frozen on the winner of a round
+pub const ROUND_END_FRAMES: u32 = 150;
+/// frames between someone voting to leave and the match closing, so their vote reaches everyone
+const LEAVE_FRAMES: u32 = 60;
+
+/// when a match is over. every peer has to agree on this
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Reflect, Hash)]
+pub enum MatchFormat {
+    /// ends after the round in which someone reaches this many points
+    ScoreLimit(u32),
+    /// ends once someone has won most of this many rounds
+    BestOf(u32),
+}
+
+impl Default for MatchFormat {
+    fn default() -> Self {
+        Self::BestOf(5)
+    }
+}
+
+impl MatchFormat {
+    pub fn from_options(options: &LaunchOptions) -> Self {
+        if let Some(limit) = options.parse("score-limit") {
+            return Self::ScoreLimit(limit);
+        }
+        options
+            .parse("best-of")
+            .map_or_else(Self::default, |rounds: u32| Self::BestOf(rounds.max(1)))
+    }
+
+    /// `None` while the match goes on, otherwise its winner, `Some(None)` being a draw.
+    /// `standings` are `(player, points, round wins)`
+    pub fn result(self, rounds: u32, standings: &[(usize, u32, u32)]) -> Option<Option<usize>> {
+        let leader = |score: fn(&(usize, u32, u32)) -> u32| {
+            let best = standings.iter().map(score).max()?;
+            let mut leaders = standings.iter().filter(|s| score(s) == best);
+            match (leaders.next(), leaders.next()) {
+                (Some((id, ..)), None) => Some(*id),
+                _ => None,
+            }
+        };
+        match self {
+            Self::ScoreLimit(limit) => standings
+                .iter()
+                .any(|(_, points, _)| *points >= limit)
+                .then(|| leader(|(_, points, _)| *points)),
+            Self::BestOf(rounds_total) => (rounds >= rounds_total
+                || standings.iter().any(|(.., wins)| wins * 2 > rounds_total))
+            .then(|| leader(|(.., wins)| *wins)),
+        }
+    }
+}
+
+impl std::fmt::Display for MatchFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::ScoreLimit(limit) => write!(f, "first to {limit} points"),
+            Self::BestOf(rounds) => write!(f, "best of {rounds}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Reflect, Hash)]
+pub enum Phase {
+    /// players can aim, but not move or shoot yet
+    #[default]
+    Countdown,
+    Live,
+    /// frozen on the last round's winner
+    RoundEnd,
+    /// waiting on everyone's rematch vote
+    MatchEnd,
+}
+
+/// where the match is at, rolled back with everything else
+#[derive(Resource, Clone, Copy, Debug, Reflect, Hash)]
+pub struct MatchState {
+    pub phase: Phase,
+    /// frames until the phase moves on, for the phases that time out
+    pub frames_left: u32,
+    /// rounds finished so far
+    pub rounds: u32,
+    /// who won the last round or, once it's over, the match. `None` is a draw
+    pub winner: Option<usize>,
+    /// one bit per player handle that asked for a rematch
+    pub rematch_votes: u8,
+    /// someone voted to leave, so the match closes once `frames_left` runs out
+    pub leaving: bool,
+    /// everyone goes back to the lobby
+    pub closed: bool,
+}
+
+impl Default for MatchState {
+    fn default() -> Self {
+        Self {
+            phase: Phase::Countdown,
+            frames_left: COUNTDOWN_FRAMES,
+            rounds: 0,
+            winner: None,
+            rematch_votes: 0,
+            leaving: false,
+            closed: false,
+        }
+    }
+}
+
+impl MatchState {
+    pub fn voted_rematch(&self, id: usize) -> bool {
+        self.rematch_votes & (1 << id) != 0
+    }
+}
+
+/// the vote buttons pressed in the gui, sent with every local player's input until the match moves on
+#[derive(Resource, Default, Debug)]
+pub struct LocalVote(pub u8);
+
+pub struct RoundsPlugin;
+impl Plugin for RoundsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MatchFormat>()
+            .init_resource::<LocalVote>()
+            .add_systems(OnEnter(GameState::Game), |mut vote: ResMut<LocalVote>| {
+                vote.0 = 0;
+            })
+            .add_systems(
+                Update,
+                (clear_vote, close_match)
+                    .run_if(in_state(GameState::Game).and_then(resource_exists::<MatchState>())),
+            );
+    }
+}
+
+/// nobody moves or shoots outside of a live round
+pub fn freeze_inputs(mut inputs: ResMut<PlayerInputs<GgrsConfig>>, state: Res<MatchState>) {
+    if state.phase == Phase::Live {
+        return;
+    }
+    for (input, _) in inputs.iter_mut() {
+        input.btn &= !(MOVE | FIRE);
+    }
+}
+
+/// move the match along: start rounds, end them on the first death, and end the match on its format
+pub fn advance_match(
+    mut commands: Commands,
+    mut state: ResMut<MatchState>,
+    format: Res<MatchFormat>,
+    mut fc: ResMut<GameFrameCount>,
+    inputs: Res<PlayerInputs<GgrsConfig>>,
+    mut q_player: Query<(
+        &Player,
+        &Health,
+        Option<&LastDamagedBy>,
+        &mut Points,
+        &mut RoundWins,
+    )>,
+    q_bullet: Query<Entity, With<Bullet>>,
+) {
+    match state.phase {
+        Phase::Countdown => {
+            state.frames_left = state.frames_left.saturating_sub(1);
+            if state.frames_left == 0 {
+                state.phase = Phase::Live;
+            }
+        }
+        Phase::Live => {
+            let mut dead = q_player
+                .iter()
+                .filter(|(_, health, ..)| health.0 <= 0)
+                .peekable();
+            if dead.peek().is_none() {
+                return;
+            }
+            // a round goes to whoever took every life lost in it, if they're still standing
+            let mut killers = dead.map(|(player, _, damaged_by, ..)| {
+                damaged_by.map_or(player.id, |damaged_by| damaged_by.id)
+            });
+            let first = killers.next();
+            let winner = first.filter(|first| {
+                killers.all(|killer| killer == *first)
+                    && q_player
+                        .iter()
+                        .any(|(player, health, ..)| player.id == *first && health.0 > 0)
+            });
+            for (player, .., mut wins) in &mut q_player {
+                if Some(player.id) == winner {
+                    wins.0 += 1;
+                }
+            }
+            // arrows still in the air can't change the outcome
+            for bullet in &q_bullet {
+                commands.entity(bullet).despawn_recursive();
+            }
+            state.phase = Phase::RoundEnd;
+            state.frames_left = ROUND_END_FRAMES;
+            state.winner = winner;
+        }
+        Phase::RoundEnd => {
+            state.frames_left = state.frames_left.saturating_sub(1);
+            if state.frames_left > 0 {
+                return;
+            }
+            state.rounds += 1;
+            let mut standings: Vec<(usize, u32, u32)> = q_player
+                .iter()
+                .map(|(player, _, _, points, wins)| (player.id, points.0, wins.0))
+                .collect();
+            standings.sort_unstable();
+            if let Some(winner) = format.result(state.rounds, &standings) {
+                state.phase = Phase::MatchEnd;
+                state.winner = winner;
+            } else {
+                next_round(&mut state, &mut fc);
+            }
+        }
+        Phase::MatchEnd => {
+            if state.leaving {
+                state.frames_left = state.frames_left.saturating_sub(1);
+                state.closed |= state.frames_left == 0;
+                return;
+            }
+            for (player, ..) in &q_player {
+                let (input, _) = inputs[player.id];
+                if input.btn & REMATCH != 0 {
+                    state.rematch_votes |= 1 << player.id;
+                }
+                if input.btn & LEAVE != 0 {
+                    state.leaving = true;
+                    state.frames_left = LEAVE_FRAMES;
+                }
+            }
+            let everyone = q_player
+                .iter()
+                .all(|(player, ..)| state.voted_rematch(player.id));
+            if everyone && !state.leaving {
+                for (.., mut points, mut wins) in &mut q_player {
+                    points.0 = 0;
+                    wins.0 = 0;
+                }
+                *state = MatchState::default();
+                next_round(&mut state, &mut fc);
+            }
+        }
+    }
+}
+
+/// count down into a fresh round, with everyone back at a spawnpoint
+fn next_round(state: &mut MatchState, fc: &mut GameFrameCount) {
+    state.phase = Phase::Countdown;
+    state.frames_left = COUNTDOWN_FRAMES;
+    state.winner = None;
+    // see `first_frame_init`
+    fc.0 = 0;
+}
+
+/// forget our vote once the match has moved on from it
+fn clear_vote(state: Res<MatchState>, mut vote: ResMut<LocalVote>) {
+    if state.phase != Phase::MatchEnd && vote.0 != 0 {
+        vote.0 = 0;
+    }
+}
+
+fn close_match(state: Res<MatchState>, mut next_state: ResMut<NextState<GameState>>) {
+    if state.closed {
+        info!("match closed, back to the lobby");
+        next_state.set(GameState::Lobby);
+    }
+}
+
+#[test]
+fn matches_end_on_their_format() {
+    let standings = [(0, 300, 1), (1, 500, 2)];
+    assert_eq!(MatchFormat::BestOf(5).result(3, &standings), None);
+    assert_eq!(MatchFormat::BestOf(3).result(3, &standings), Some(Some(1)));
+    assert_eq!(
+        MatchFormat::ScoreLimit(500).result(3, &standings),
+        Some(Some(1))
+    );
+    // level on round wins after every round was played
+    let tied = [(0, 300, 2), (1, 500, 2)];
+    assert_eq!(MatchFormat::BestOf(4).result(4, &tied), Some(None));
+}
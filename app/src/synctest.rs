@@ -0,0 +1,185 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_ggrs::*;
+
+use crate::{
+    checksum::FrameChecksums,
+    config::LaunchOptions,
+    input::{PlayerInput, FIRE, MOVE, SWITCH},
+    p2p::{GgrsConfig, LocalPlayer, NetworkConfig, MAX_PLAYERS},
+    rand::Rng,
+    GameState,
+};
+
+/// ggrs refuses check distances at or beyond its prediction window of 8
+pub const MAX_CHECK_DISTANCE: usize = 7;
+
+/// offline determinism checking: every frame is rolled back and resimulated
+/// `check_distance` frames later, and must come out the same
+#[derive(Resource, Debug, Clone)]
+pub struct SyncTestConfig {
+    pub check_distance: usize,
+    /// drive every player with generated inputs instead of the local keyboard and mouse
+    pub scripted: bool,
+    /// start a session next time we're in the lobby
+    pub requested: bool,
+}
+
+impl FromWorld for SyncTestConfig {
+    fn from_world(world: &mut World) -> Self {
+        let options = world
+            .get_resource::<LaunchOptions>()
+            .cloned()
+            .unwrap_or_default();
+        Self {
+            check_distance: options
+                .parse("check-distance")
+                .unwrap_or(2)
+                .clamp(1, MAX_CHECK_DISTANCE),
+            scripted: options.get("inputs") == Some("scripted"),
+            requested: options.parse("synctest").unwrap_or(false),
+        }
+    }
+}
+
+/// frames that came out differently when resimulated
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    pub frame: i32,
+    pub components: Vec<&'static str>,
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct SyncTestReport {
+    pub frames_checked: usize,
+    pub mismatches: Vec<Mismatch>,
+    history: HashMap<i32, FrameChecksums>,
+}
+
+pub struct SyncTestPlugin;
+impl Plugin for SyncTestPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SyncTestConfig>()
+            .add_systems(
+                Update,
+                start_synctest.run_if(
+                    in_state(GameState::Lobby)
+                        .and_then(|config: Res<SyncTestConfig>| config.requested),
+                ),
+            )
+            .add_systems(
+                OnEnter(GameState::Game),
+                start_synctest_session
+                    .before(crate::spawn_players)
+                    .run_if(resource_exists::<SyncTestReport>()),
+            )
+            .add_systems(ReadInputs, scripted_input.run_if(scripted_inputs))
+            .add_systems(
+                GgrsSchedule,
+                check_frame
+                    .in_set(crate::InspectFrame)
+                    .run_if(resource_exists::<SyncTestReport>()),
+            );
+    }
+}
+
+/// whether players are driven by `scripted_input` rather than the local devices.
+/// ggrs takes the session out of the world while reading inputs, so go by the report instead
+pub fn scripted_inputs(config: Res<SyncTestConfig>, report: Option<Res<SyncTestReport>>) -> bool {
+    config.scripted && report.is_some()
+}
+
+/// skip the signaling server and play every handle locally
+fn start_synctest(
+    mut commands: Commands,
+    mut config: ResMut<SyncTestConfig>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    config.requested = false;
+    // the camera and scoreboard follow the first player
+    commands.insert_resource(LocalPlayer { id: 0 });
+    commands.insert_resource(SyncTestReport::default());
+    next_state.set(GameState::Game);
+}
+
+/// only start the session once in game, it would otherwise run (and save) frames without any players
+fn start_synctest_session(world: &mut World) {
+    let check_distance = world.resource::<SyncTestConfig>().check_distance;
+    let network = world.resource::<NetworkConfig>();
+    // bots are checked along with everyone else
+    let mut bots = network.bots;
+    bots.count = bots.count.min(MAX_PLAYERS - network.players);
+    let format = network.format;
+    let health = network.health;
+    info!(
+        "starting synctest with {} players and {} bots, check distance {check_distance}",
+        network.players, bots.count
+    );
+    let session = ggrs::SessionBuilder::<GgrsConfig>::new()
+        .with_num_players(network.players + bots.count)
+        .with_check_distance(check_distance)
+        .start_synctest_session()
+        .expect("Could not start synctest session.");
+    world.insert_resource(Session::SyncTest(session));
+    world.insert_resource(bots);
+    world.insert_resource(format);
+    world.insert_resource(health);
+}
+
+/// pseudo-random inputs derived from the frame, so a synctest can run unattended
+fn scripted_input(
+    mut commands: Commands,
+    local_players: Res<LocalPlayers>,
+    frame: Res<RollbackFrameCount>,
+) {
+    let frame = i32::from(*frame).max(0) as u64;
+    let local_inputs = local_players
+        .0
+        .iter()
+        .map(|&handle| {
+            // hold each direction for half a second, so players actually get somewhere
+            let seed = (frame / 30 * 8 + handle as u64).wrapping_mul(2_654_435_761);
+            let mut rng = Rng::new(seed % 2_147_483_646 + 1);
+            let mut btn = 0;
+            if rng.next_f32() < 0.8 {
+                btn |= MOVE;
+            }
+            if (frame + handle as u64 * 7) % 30 < 10 {
+                btn |= FIRE;
+            }
+            // try out every weapon now and then
+            if (frame + handle as u64 * 13) % 200 < 5 {
+                btn |= SWITCH;
+            }
+            let input = PlayerInput {
+                dir: rng.next_usize(0, 256) as u8,
+                btn,
+                angle: (frame * 3 + handle as u64 * 32) as u8,
+            };
+            (handle, input)
+        })
+        .collect();
+    commands.insert_resource(LocalInputs::<GgrsConfig>(local_inputs));
+}
+
+/// compare the state at the end of this frame to the last time it was simulated
+pub fn check_frame(world: &mut World) {
+    let frame = i32::from(*world.resource::<RollbackFrameCount>());
+    let checksums = FrameChecksums::of(world);
+    let mut report = world.resource_mut::<SyncTestReport>();
+    if let Some(previous) = report.history.get(&frame) {
+        let components = previous.mismatches(&checksums);
+        report.frames_checked += 1;
+        if !components.is_empty() {
+            error!(
+                "synctest mismatch on frame {frame}, resimulating changed {}",
+                components.join(", ")
+            );
+            report.mismatches.push(Mismatch { frame, components });
+        }
+    }
+    // frames older than the check distance won't be resimulated again
+    report
+        .history
+        .retain(|f, _| *f > frame - MAX_CHECK_DISTANCE as i32 - 1);
+    report.history.insert(frame, checksums);
+}
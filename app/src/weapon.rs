@@ -0,0 +1,332 @@
+//! weapons are data, described in `assets/weapons.ron`. every peer has to load the same file,
+//! the simulation only ever refers to a weapon by its place in it
+
+use anyhow::anyhow;
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    audio::{PlaybackMode, Volume, VolumeLevel},
+    prelude::*,
+    reflect::TypePath,
+    utils::BoxedFuture,
+};
+use bevy_ggrs::PlayerInputs;
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    ammo::QUIVER_SIZE,
+    component::{Bow, CanShoot, Player},
+    fixed::Fixed,
+    p2p::GgrsConfig,
+    rand::Rng,
+    GameState,
+};
+
+pub const WEAPONS_PATH: &str = "weapons.ron";
+
+pub struct WeaponPlugin;
+impl Plugin for WeaponPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_asset_loader(ArsenalLoader)
+            .init_asset::<Arsenal>()
+            .register_type::<Equipped>()
+            .add_systems(OnExit(GameState::Loading), setup_arsenal);
+    }
+}
+
+/// one kind of weapon, and the projectiles it fires
+#[derive(Deserialize, Clone, Debug)]
+pub struct Weapon {
+    pub name: String,
+    /// what the player holds, a 2x2 sheet of 16px frames like `bow.png`
+    pub sprite: String,
+    pub projectile_sprite: String,
+    /// distance a projectile flies per frame
+    #[serde(deserialize_with = "fixed")]
+    pub speed: Fixed,
+    /// frames a projectile flies before disappearing
+    pub lifetime: usize,
+    /// health a hit takes
+    pub damage: i32,
+    /// frames between two shots
+    pub cooldown: usize,
+    /// input angle steps, 255 being a full turn, the projectiles fan out over. a single
+    /// projectile is thrown off by a random amount within it instead
+    pub spread: u8,
+    pub projectiles: u8,
+    /// frames fire has to be held for a full strength shot, which goes off on letting go.
+    /// without, the weapon fires as soon as fire is pressed
+    #[serde(default)]
+    pub charge_frames: usize,
+    /// the share of speed and damage a quick tap gets, from a charge it grows to the full amount
+    #[serde(default = "full_power", deserialize_with = "fixed")]
+    pub tap_power: Fixed,
+    pub on_impact: Impact,
+}
+
+/// what a projectile does on running into terrain
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub enum Impact {
+    /// glance off, up to `max_bounces` times, then drop
+    Bounce { max_bounces: u8 },
+    /// stop dead in the wall and stay there, harmless, for `linger` frames
+    Stick { linger: usize },
+    /// shatter, playing `sound`
+    Break { sound: String },
+}
+
+impl Weapon {
+    /// how far a projectile gets before it disappears, at full power
+    pub fn range(&self) -> Fixed {
+        self.speed * Fixed::from_int(self.lifetime as i64)
+    }
+
+    /// how strong a shot is after charging it for `charge` frames
+    pub fn power(&self, charge: usize) -> Fixed {
+        if self.charge_frames == 0 {
+            return Fixed::ONE;
+        }
+        let charged = Fixed::from_ratio(
+            charge.min(self.charge_frames) as i64,
+            self.charge_frames as i64,
+        );
+        self.tap_power + (Fixed::ONE - self.tap_power) * charged
+    }
+
+    /// the input angles a shot aimed at `angle` sends each projectile off at
+    pub fn projectile_angles(&self, angle: u8, rng: &mut Rng) -> Vec<u8> {
+        let spread = self.spread as i64;
+        let count = self.projectiles as i64;
+        let offsets: Vec<i64> = match count {
+            1 if spread == 0 => vec![0],
+            1 => vec![rng.next_i32(0, spread as i32 + 1) as i64 - spread / 2],
+            // evenly from one edge of the spread to the other
+            _ => (0..count)
+                .map(|i| i * spread / (count - 1) - spread / 2)
+                .collect(),
+        };
+        // 255 is a full turn
+        offsets
+            .into_iter()
+            .map(|offset| (angle as i64 + offset).rem_euclid(255) as u8)
+            .collect()
+    }
+}
+
+/// every weapon a player can switch between, in the order they cycle through
+#[derive(Asset, Resource, TypePath, Deserialize, Clone, Debug)]
+pub struct Arsenal {
+    pub weapons: Vec<Weapon>,
+}
+
+impl Arsenal {
+    /// the weapon a player holds. `Equipped` only ever points into the arsenal
+    pub fn get(&self, equipped: &Equipped) -> &Weapon {
+        &self.weapons[equipped.weapon as usize]
+    }
+}
+
+/// sprites for each weapon of the arsenal, by the same index
+#[derive(Resource, Debug)]
+pub struct WeaponSprites {
+    pub held: Vec<Handle<TextureAtlas>>,
+    pub projectiles: Vec<Handle<Image>>,
+}
+
+/// which weapon of the arsenal a player holds
+#[derive(Component, Clone, Copy, Default, Debug, Reflect, Hash)]
+pub struct Equipped {
+    pub weapon: u8,
+    /// the switch button was already down last frame, holding it only switches once
+    pub switch_held: bool,
+}
+
+fn full_power() -> Fixed {
+    Fixed::ONE
+}
+
+/// the weapons file holds speeds as decimals, which every peer reads the same way
+fn fixed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Fixed, D::Error> {
+    f32::deserialize(deserializer).map(Fixed::from_f32)
+}
+
+struct ArsenalLoader;
+impl AssetLoader for ArsenalLoader {
+    type Asset = Arsenal;
+    type Settings = ();
+    type Error = anyhow::Error;
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a Self::Settings,
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<Self::Asset>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            parse_arsenal(&bytes)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ron"]
+    }
+}
+
+fn parse_arsenal(bytes: &[u8]) -> anyhow::Result<Arsenal> {
+    let arsenal: Arsenal = ron::de::from_bytes(bytes)?;
+    if arsenal.weapons.is_empty() || arsenal.weapons.len() > u8::MAX as usize {
+        return Err(anyhow!("Need between 1 and 255 weapons"));
+    }
+    if let Some(weapon) = arsenal.weapons.iter().find(|w| w.projectiles == 0) {
+        return Err(anyhow!("{} doesn't fire anything", weapon.name));
+    }
+    if let Some(weapon) = arsenal.weapons.iter().find(|w| w.projectiles > QUIVER_SIZE) {
+        return Err(anyhow!("{} fires more than a quiver holds", weapon.name));
+    }
+    if let Some(weapon) = arsenal.weapons.iter().find(|w| w.speed <= Fixed::ZERO) {
+        return Err(anyhow!("{}'s projectiles don't go anywhere", weapon.name));
+    }
+    if let Some(weapon) = arsenal
+        .weapons
+        .iter()
+        .find(|w| w.lifetime == 0 || w.on_impact == Impact::Stick { linger: 0 })
+    {
+        return Err(anyhow!("{}'s projectiles vanish right away", weapon.name));
+    }
+    Ok(arsenal)
+}
+
+/// the simulation reads the arsenal as a resource, loaded by the time we leave the loading screen
+fn setup_arsenal(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    arsenals: Res<Assets<Arsenal>>,
+    mut atlases: ResMut<Assets<TextureAtlas>>,
+) {
+    let handle: Handle<Arsenal> = asset_server.load(WEAPONS_PATH);
+    let arsenal = arsenals
+        .get(&handle)
+        .expect("The weapons should have loaded.")
+        .clone();
+    let held = arsenal
+        .weapons
+        .iter()
+        .map(|weapon| {
+            let image = asset_server.load(&weapon.sprite);
+            atlases.add(TextureAtlas::from_grid(
+                image,
+                Vec2::splat(16.),
+                2,
+                2,
+                None,
+                None,
+            ))
+        })
+        .collect();
+    let projectiles = arsenal
+        .weapons
+        .iter()
+        .map(|weapon| asset_server.load(&weapon.projectile_sprite))
+        .collect();
+    commands.insert_resource(WeaponSprites { held, projectiles });
+    commands.insert_resource(arsenal);
+}
+
+/// cycle to the next weapon on pressing switch, which has to be drawn before it can fire
+pub fn switch_weapon(
+    inputs: Res<PlayerInputs<GgrsConfig>>,
+    arsenal: Res<Arsenal>,
+    mut q_player: Query<(&Player, &mut Equipped, &mut CanShoot)>,
+) {
+    for (player, mut equipped, mut can_shoot) in &mut q_player {
+        let (input, _) = inputs[player.id];
+        let pressed = input.switch_weapon();
+        if pressed && !equipped.switch_held {
+            equipped.weapon = (equipped.weapon + 1) % arsenal.weapons.len() as u8;
+            can_shoot.since_last = 0;
+            can_shoot.charge = 0;
+        }
+        equipped.switch_held = pressed;
+    }
+}
+
+/// hand players the sprite of whatever they switched to
+pub fn show_equipped_weapon(
+    sprites: Res<WeaponSprites>,
+    q_player: Query<&Equipped, Changed<Equipped>>,
+    mut q_bow: Query<(&mut Handle<TextureAtlas>, &Parent), With<Bow>>,
+) {
+    for (mut atlas, parent) in &mut q_bow {
+        let Ok(equipped) = q_player.get(parent.get()) else {
+            continue;
+        };
+        let held = &sprites.held[equipped.weapon as usize];
+        if *atlas != *held {
+            *atlas = held.clone();
+        }
+    }
+}
+
+/// the sound of a shot being charged, following the player charging it
+#[derive(Component)]
+pub struct ChargeSound {
+    player: Entity,
+}
+
+/// play the charge sound while players charge, rising in pitch along with the charge
+pub fn charge_sound(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    arsenal: Res<Arsenal>,
+    q_player: Query<(Entity, &CanShoot, &Equipped), With<Player>>,
+    q_sound: Query<(Entity, &ChargeSound, Option<&AudioSink>)>,
+) {
+    for (sound, charge_sound, sink) in &q_sound {
+        match q_player.get(charge_sound.player) {
+            Ok((_, can_shoot, equipped)) if can_shoot.charge > 0 => {
+                let power = arsenal.get(equipped).power(can_shoot.charge);
+                if let Some(sink) = sink {
+                    sink.set_speed(0.5 + power.to_f32());
+                }
+            }
+            // let go, or gone altogether
+            _ => commands.entity(sound).despawn(),
+        }
+    }
+    for (player, can_shoot, _) in &q_player {
+        let playing = q_sound.iter().any(|(_, sound, _)| sound.player == player);
+        if can_shoot.charge > 0 && !playing {
+            commands.spawn((
+                ChargeSound { player },
+                AudioBundle {
+                    source: asset_server.load("sfx/Bow_Charge.wav"),
+                    settings: PlaybackSettings {
+                        mode: PlaybackMode::Despawn,
+                        volume: Volume::Relative(VolumeLevel::new(0.2)),
+                        ..default()
+                    },
+                },
+            ));
+        }
+    }
+}
+
+#[test]
+fn the_bundled_weapons_parse() {
+    let arsenal = parse_arsenal(include_bytes!("../../assets/weapons.ron")).unwrap();
+    let bow = &arsenal.weapons[0];
+    assert_eq!(bow.speed, Fixed::from_ratio(5, 2));
+    assert_eq!(bow.cooldown, 25);
+    assert_eq!(bow.power(bow.charge_frames * 2), Fixed::ONE);
+    assert!(bow.power(1) < Fixed::from_ratio(1, 2));
+    assert!(parse_arsenal(b"(weapons: [])").is_err());
+    let limp = r#"(weapons: [(name: "Limp", sprite: "bow.png", projectile_sprite: "arrow.png",
+        speed: 0.0, lifetime: 60, damage: 1, cooldown: 20, spread: 0, projectiles: 1,
+        on_impact: Stick(linger: 300))])"#;
+    assert!(parse_arsenal(limp.as_bytes()).is_err());
+    assert!(parse_arsenal(limp.replace("speed: 0.0", "speed: 1.0").as_bytes()).is_ok());
+    let volley = &arsenal.weapons[1];
+    let mut rng = Rng::default();
+    assert_eq!(volley.projectile_angles(2, &mut rng), vec![245, 2, 14]);
+    assert!(matches!(bow.on_impact, Impact::Stick { .. }));
+}
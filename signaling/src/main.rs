@@ -1,3 +1,53 @@
-fn main() {
-    println!("not implemented")
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    routing::{get, post},
+    Router,
+};
+use clap::Parser;
+use tracing_subscriber::EnvFilter;
+
+mod socket;
+mod state;
+
+use state::ServerState;
+
+pub type SharedState = Arc<Mutex<ServerState>>;
+
+/// matchbox-compatible webrtc signaling server for p2pg
+#[derive(Parser, Debug)]
+struct Args {
+    /// address to listen on
+    #[arg(long, env = "SIGNALING_HOST", default_value = "0.0.0.0:3536")]
+    host: SocketAddr,
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let args = Args::parse();
+    let state = SharedState::default();
+
+    let app = Router::new()
+        .route("/", get(socket::ws_handler))
+        .route("/rooms", get(socket::rooms_handler))
+        .route("/tickets", post(socket::tickets_handler))
+        .route("/:room", get(socket::ws_handler))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(args.host)
+        .await
+        .expect("Could not bind signaling server address.");
+    tracing::info!("signaling server listening on {}", args.host);
+    axum::serve(listener, app)
+        .await
+        .expect("Signaling server stopped unexpectedly.");
 }
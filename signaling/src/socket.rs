@@ -0,0 +1,133 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::{SinkExt, StreamExt};
+use matchbox_protocol::{JsonPeerEvent, JsonPeerRequest, PeerId};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{
+    state::{RequestedRoom, RoomQuery, RoomSettings},
+    SharedState,
+};
+
+/// the named rooms that can still be joined, as json
+pub async fn rooms_handler(State(state): State<SharedState>) -> Response {
+    let rooms = state.lock().unwrap().list_rooms();
+    // the browser build fetches this from wherever the game is hosted
+    ([(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")], Json(rooms)).into_response()
+}
+
+/// trade the password in the request body for a ticket to join a room with
+pub async fn tickets_handler(State(state): State<SharedState>, password: String) -> Response {
+    let ticket = state.lock().unwrap().issue_ticket(password);
+    ([(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")], ticket).into_response()
+}
+
+/// upgrade an incoming http request on `/<room>?next=N` to a signaling websocket
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    room: Option<Path<String>>,
+    Query(query): Query<RoomQuery>,
+    State(state): State<SharedState>,
+) -> Response {
+    let requested = RequestedRoom {
+        id: room.map(|Path(id)| id).unwrap_or_default(),
+        next: query.next.filter(|n| *n > 0),
+    };
+    let mut state_guard = state.lock().unwrap();
+    let password = query
+        .ticket
+        .and_then(|ticket| state_guard.redeem_ticket(&ticket))
+        .filter(|password| !password.is_empty());
+    let settings = RoomSettings {
+        name: query.name.filter(|name| !name.is_empty()),
+        map: query.map,
+        password,
+    };
+    // turned away before upgrading, so the client sees why
+    if !state_guard.can_join(&requested, settings.password.as_deref()) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    drop(state_guard);
+    let spectator = query.spectate;
+    ws.on_upgrade(move |socket| handle_socket(socket, requested, settings, spectator, state))
+}
+
+/// lifetime of a single peer's connection
+async fn handle_socket(
+    socket: WebSocket,
+    requested: RequestedRoom,
+    settings: RoomSettings,
+    spectator: bool,
+    state: SharedState,
+) {
+    let (mut ws_tx, mut ws_rx) = socket.split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+
+    let id = PeerId(Uuid::new_v4());
+    tracing::info!("peer {id} joined room {:?}", requested);
+
+    // the id must be the first event the peer receives
+    tx.send(JsonPeerEvent::IdAssigned(id).to_string())
+        .expect("receiver dropped before use");
+    let joined =
+        state
+            .lock()
+            .unwrap()
+            .add_peer(id, requested.clone(), settings, spectator, tx.clone());
+    if let Err(e) = joined {
+        // the room got a password between the http request and the upgrade
+        tracing::info!("peer {id} turned away from room {:?}: {e:?}", requested);
+        return;
+    }
+
+    // forward queued events to the websocket
+    let mut send_task = tokio::spawn(async move {
+        while let Some(text) = rx.recv().await {
+            if ws_tx.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // handle requests coming from the peer
+    let recv_state = state.clone();
+    let mut recv_task = tokio::spawn(async move {
+        while let Some(Ok(message)) = ws_rx.next().await {
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            let request: JsonPeerRequest = match text.parse() {
+                Ok(request) => request,
+                Err(e) => {
+                    tracing::warn!("malformed request from {id}: {e}");
+                    continue;
+                }
+            };
+            match request {
+                JsonPeerRequest::Signal { receiver, data } => {
+                    recv_state.lock().unwrap().relay(id, receiver, data);
+                }
+                JsonPeerRequest::KeepAlive => (),
+            }
+        }
+    });
+
+    // whichever side closes first ends the connection
+    tokio::select! {
+        _ = &mut send_task => recv_task.abort(),
+        _ = &mut recv_task => send_task.abort(),
+    }
+
+    state.lock().unwrap().remove_peer(id);
+    tracing::info!("peer {id} left room {:?}", requested);
+}
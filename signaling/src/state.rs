@@ -0,0 +1,408 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use matchbox_protocol::{JsonPeerEvent, PeerId};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// the room a client asked for in its connection url, i.e. `/p2pg?next=2`
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct RequestedRoom {
+    pub id: String,
+    pub next: Option<usize>,
+}
+
+/// query parameters accepted on the websocket route
+#[derive(Deserialize, Debug, Default)]
+pub struct RoomQuery {
+    pub next: Option<usize>,
+    /// spectators join a room without taking up one of its `next` slots
+    #[serde(default)]
+    pub spectate: bool,
+    /// naming a room lists it in the room browser
+    pub name: Option<String>,
+    pub map: Option<String>,
+    /// traded for a password over `/tickets`, so passwords stay out of urls and logs
+    pub ticket: Option<String>,
+}
+
+/// how long a ticket can be used to join a room after it was handed out
+const TICKET_TTL: Duration = Duration::from_secs(60);
+
+/// how the peer that opens a room describes it
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct RoomSettings {
+    pub name: Option<String>,
+    pub map: Option<String>,
+    pub password: Option<String>,
+}
+
+/// a listed room, as served by the `/rooms` endpoint
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct RoomInfo {
+    /// the path to connect to, with `?next=<capacity>`
+    pub id: String,
+    pub name: String,
+    pub map: Option<String>,
+    pub players: usize,
+    pub capacity: Option<usize>,
+    pub password: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum JoinError {
+    WrongPassword,
+}
+
+/// a group of peers that are told about each other
+#[derive(Debug)]
+struct Room {
+    peers: Vec<PeerId>,
+    players: usize,
+    capacity: Option<usize>,
+    settings: RoomSettings,
+}
+
+impl Room {
+    fn is_full(&self) -> bool {
+        self.capacity.is_some_and(|cap| self.players >= cap)
+    }
+}
+
+#[derive(Debug)]
+struct Client {
+    room: u64,
+    spectator: bool,
+    sender: UnboundedSender<String>,
+}
+
+#[derive(Default, Debug)]
+pub struct ServerState {
+    clients: HashMap<PeerId, Client>,
+    rooms: HashMap<u64, Room>,
+    // the room currently accepting new peers for each requested room
+    open: HashMap<RequestedRoom, u64>,
+    next_room: u64,
+    // passwords waiting to be picked up by the peer we handed the ticket to
+    tickets: HashMap<String, (String, Instant)>,
+}
+
+impl ServerState {
+    /// whether `password` lets a peer into the requested room, always true for rooms not opened yet
+    pub fn can_join(&self, requested: &RequestedRoom, password: Option<&str>) -> bool {
+        let Some(room) = self.open.get(requested).and_then(|id| self.rooms.get(id)) else {
+            return true;
+        };
+        room.settings.password.is_none() || room.settings.password.as_deref() == password
+    }
+
+    /// register a new peer in the requested room, notifying everyone already in it.
+    /// `?next=N` rooms are sealed once N peers have joined, so the next peer starts a fresh one.
+    /// the first peer in a room decides its settings, everyone after needs its password
+    pub fn add_peer(
+        &mut self,
+        id: PeerId,
+        requested: RequestedRoom,
+        settings: RoomSettings,
+        spectator: bool,
+        sender: UnboundedSender<String>,
+    ) -> Result<Vec<PeerId>, JoinError> {
+        if !self.can_join(&requested, settings.password.as_deref()) {
+            return Err(JoinError::WrongPassword);
+        }
+        let room_id = match self.open.get(&requested) {
+            Some(room_id) => *room_id,
+            None => {
+                let room_id = self.next_room;
+                self.next_room += 1;
+                self.rooms.insert(
+                    room_id,
+                    Room {
+                        peers: vec![],
+                        players: 0,
+                        capacity: requested.next,
+                        settings,
+                    },
+                );
+                self.open.insert(requested.clone(), room_id);
+                room_id
+            }
+        };
+
+        let room = self.rooms.get_mut(&room_id).expect("open room missing");
+        let existing = room.peers.clone();
+        room.peers.push(id);
+        if !spectator {
+            room.players += 1;
+        }
+        if room.is_full() {
+            self.open.remove(&requested);
+        }
+
+        self.clients.insert(
+            id,
+            Client {
+                room: room_id,
+                spectator,
+                sender,
+            },
+        );
+        for peer in &existing {
+            self.send(*peer, JsonPeerEvent::NewPeer(id));
+        }
+        Ok(existing)
+    }
+
+    /// hand out a ticket that stands in for `password` when joining, usable once
+    pub fn issue_ticket(&mut self, password: String) -> String {
+        let now = Instant::now();
+        self.tickets
+            .retain(|_, (_, issued)| now.duration_since(*issued) < TICKET_TTL);
+        let ticket = uuid::Uuid::new_v4().to_string();
+        self.tickets.insert(ticket.clone(), (password, now));
+        ticket
+    }
+
+    /// the password a ticket was issued for, if it's still good
+    pub fn redeem_ticket(&mut self, ticket: &str) -> Option<String> {
+        let (password, issued) = self.tickets.remove(ticket)?;
+        (issued.elapsed() < TICKET_TTL).then_some(password)
+    }
+
+    /// named rooms that still have space, sorted by name
+    pub fn list_rooms(&self) -> Vec<RoomInfo> {
+        let mut rooms: Vec<RoomInfo> = self
+            .open
+            .iter()
+            .filter_map(|(requested, room_id)| {
+                let room = self.rooms.get(room_id)?;
+                Some(RoomInfo {
+                    id: requested.id.clone(),
+                    name: room.settings.name.clone()?,
+                    map: room.settings.map.clone(),
+                    players: room.players,
+                    capacity: room.capacity,
+                    password: room.settings.password.is_some(),
+                })
+            })
+            .collect();
+        rooms.sort_by(|a, b| (&a.name, &a.id).cmp(&(&b.name, &b.id)));
+        rooms
+    }
+
+    /// remove a peer, notifying the rest of its room
+    pub fn remove_peer(&mut self, id: PeerId) -> Vec<PeerId> {
+        let Some(client) = self.clients.remove(&id) else {
+            return vec![];
+        };
+        let Some(room) = self.rooms.get_mut(&client.room) else {
+            return vec![];
+        };
+        room.peers.retain(|peer| *peer != id);
+        if !client.spectator {
+            room.players -= 1;
+        }
+        let remaining = room.peers.clone();
+        if remaining.is_empty() {
+            self.rooms.remove(&client.room);
+            self.open.retain(|_, room_id| *room_id != client.room);
+        }
+        for peer in &remaining {
+            self.send(*peer, JsonPeerEvent::PeerLeft(id));
+        }
+        remaining
+    }
+
+    /// relay a signal (sdp offer / answer, ice candidate) between two peers in the same room
+    pub fn relay(&self, sender: PeerId, receiver: PeerId, data: serde_json::Value) {
+        let same_room = match (self.clients.get(&sender), self.clients.get(&receiver)) {
+            (Some(a), Some(b)) => a.room == b.room,
+            _ => false,
+        };
+        if !same_room {
+            tracing::warn!("dropping signal from {sender} to unknown peer {receiver}");
+            return;
+        }
+        self.send(receiver, JsonPeerEvent::Signal { sender, data });
+    }
+
+    fn send(&self, id: PeerId, event: JsonPeerEvent) {
+        let Some(client) = self.clients.get(&id) else {
+            return;
+        };
+        if client.sender.send(event.to_string()).is_err() {
+            tracing::warn!("could not send event to {id}, connection closed");
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_peer() -> (
+    PeerId,
+    tokio::sync::mpsc::UnboundedReceiver<String>,
+    UnboundedSender<String>,
+) {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    (PeerId(uuid::Uuid::new_v4()), rx, tx)
+}
+
+#[test]
+fn next_rooms_are_sealed_when_full() {
+    let mut state = ServerState::default();
+    let room = RequestedRoom {
+        id: "p2pg".to_owned(),
+        next: Some(2),
+    };
+    let (a, mut a_rx, a_tx) = test_peer();
+    let (b, _b_rx, b_tx) = test_peer();
+    let (c, _c_rx, c_tx) = test_peer();
+
+    assert!(state
+        .add_peer(a, room.clone(), RoomSettings::default(), false, a_tx)
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        state
+            .add_peer(b, room.clone(), RoomSettings::default(), false, b_tx)
+            .unwrap(),
+        vec![a]
+    );
+    // a third peer starts a new game instead of joining the full one
+    assert!(state
+        .add_peer(c, room.clone(), RoomSettings::default(), false, c_tx)
+        .unwrap()
+        .is_empty());
+
+    let event: JsonPeerEvent = a_rx.try_recv().unwrap().parse().unwrap();
+    assert_eq!(event, JsonPeerEvent::NewPeer(b));
+
+    assert_eq!(state.remove_peer(b), vec![a]);
+    let event: JsonPeerEvent = a_rx.try_recv().unwrap().parse().unwrap();
+    assert_eq!(event, JsonPeerEvent::PeerLeft(b));
+}
+
+#[test]
+fn signals_are_relayed_within_a_room() {
+    let mut state = ServerState::default();
+    let room = RequestedRoom {
+        id: "p2pg".to_owned(),
+        next: None,
+    };
+    let (a, _a_rx, a_tx) = test_peer();
+    let (b, mut b_rx, b_tx) = test_peer();
+    state
+        .add_peer(a, room.clone(), RoomSettings::default(), false, a_tx)
+        .unwrap();
+    state
+        .add_peer(b, room.clone(), RoomSettings::default(), false, b_tx)
+        .unwrap();
+
+    state.relay(a, b, serde_json::json!("offer"));
+    let event: JsonPeerEvent = b_rx.try_recv().unwrap().parse().unwrap();
+    assert_eq!(
+        event,
+        JsonPeerEvent::Signal {
+            sender: a,
+            data: serde_json::json!("offer")
+        }
+    );
+}
+
+#[test]
+fn spectators_do_not_fill_rooms() {
+    let mut state = ServerState::default();
+    let room = RequestedRoom {
+        id: "p2pg".to_owned(),
+        next: Some(2),
+    };
+    let (a, _a_rx, a_tx) = test_peer();
+    let (s, _s_rx, s_tx) = test_peer();
+    let (b, _b_rx, b_tx) = test_peer();
+
+    state
+        .add_peer(a, room.clone(), RoomSettings::default(), false, a_tx)
+        .unwrap();
+    assert_eq!(
+        state
+            .add_peer(s, room.clone(), RoomSettings::default(), true, s_tx)
+            .unwrap(),
+        vec![a]
+    );
+    assert_eq!(
+        state
+            .add_peer(b, room.clone(), RoomSettings::default(), false, b_tx)
+            .unwrap(),
+        vec![a, s]
+    );
+}
+
+#[test]
+fn named_rooms_are_listed_and_password_protected() {
+    let mut state = ServerState::default();
+    let room = RequestedRoom {
+        id: "p2pg-abc".to_owned(),
+        next: Some(2),
+    };
+    let settings = RoomSettings {
+        name: Some("snow fight".to_owned()),
+        map: Some("snowy.tmx".to_owned()),
+        password: Some("hunter2".to_owned()),
+    };
+    let (a, _a_rx, a_tx) = test_peer();
+    let (b, _b_rx, b_tx) = test_peer();
+    let (q, _q_rx, q_tx) = test_peer();
+
+    state
+        .add_peer(a, room.clone(), settings, false, a_tx)
+        .unwrap();
+    // quick play pools have no name, so stay unlisted
+    let pool = RequestedRoom {
+        id: "p2pg".to_owned(),
+        next: Some(2),
+    };
+    state
+        .add_peer(q, pool, RoomSettings::default(), false, q_tx)
+        .unwrap();
+    assert_eq!(
+        state.list_rooms(),
+        vec![RoomInfo {
+            id: "p2pg-abc".to_owned(),
+            name: "snow fight".to_owned(),
+            map: Some("snowy.tmx".to_owned()),
+            players: 1,
+            capacity: Some(2),
+            password: true,
+        }]
+    );
+
+    assert!(!state.can_join(&room, None));
+    assert_eq!(
+        state.add_peer(
+            b,
+            room.clone(),
+            RoomSettings::default(),
+            false,
+            b_tx.clone()
+        ),
+        Err(JoinError::WrongPassword)
+    );
+    let password = RoomSettings {
+        password: Some("hunter2".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(state.add_peer(b, room, password, false, b_tx), Ok(vec![a]));
+    // full rooms drop off the list
+    assert!(state.list_rooms().is_empty());
+}
+
+#[test]
+fn tickets_stand_in_for_passwords_once() {
+    let mut state = ServerState::default();
+    let ticket = state.issue_ticket("hunter2".to_owned());
+    assert_ne!(ticket, "hunter2");
+    assert_eq!(state.redeem_ticket(&ticket).as_deref(), Some("hunter2"));
+    assert_eq!(state.redeem_ticket(&ticket), None);
+    assert_eq!(state.redeem_ticket("made up"), None);
+}